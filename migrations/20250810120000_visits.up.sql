@@ -0,0 +1,10 @@
+CREATE TABLE visits (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    visited_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    client_ip TEXT,
+    user_agent TEXT,
+    referer TEXT
+);
+
+CREATE INDEX visits_name_visited_at_idx ON visits (name, visited_at);
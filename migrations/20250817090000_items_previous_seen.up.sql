@@ -0,0 +1,3 @@
+-- Keeps the last_seen value that was overwritten by the most recent visit, so
+-- the upsert can report the previous state from its own RETURNING clause.
+ALTER TABLE items ADD COLUMN previous_seen TIMESTAMP;
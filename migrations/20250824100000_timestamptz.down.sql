@@ -0,0 +1,4 @@
+ALTER TABLE items
+    ALTER COLUMN last_seen TYPE TIMESTAMP USING last_seen AT TIME ZONE 'UTC',
+    ALTER COLUMN last_seen SET DEFAULT CURRENT_TIMESTAMP,
+    ALTER COLUMN previous_seen TYPE TIMESTAMP USING previous_seen AT TIME ZONE 'UTC';
@@ -0,0 +1,7 @@
+-- last_seen was stored as TIMESTAMP (without time zone). Every write came from
+-- NOW() on a sqlx connection, whose session TimeZone is UTC, so the existing
+-- values are UTC wall-clock times and are converted as such.
+ALTER TABLE items
+    ALTER COLUMN last_seen TYPE TIMESTAMPTZ USING last_seen AT TIME ZONE 'UTC',
+    ALTER COLUMN last_seen SET DEFAULT CURRENT_TIMESTAMP,
+    ALTER COLUMN previous_seen TYPE TIMESTAMPTZ USING previous_seen AT TIME ZONE 'UTC';
@@ -0,0 +1,7 @@
+-- Maps an alternative spelling to the canonical name its visits are counted under.
+CREATE TABLE aliases (
+    alias TEXT PRIMARY KEY,
+    name TEXT NOT NULL
+);
+
+CREATE INDEX aliases_name_idx ON aliases (name);
@@ -0,0 +1,14 @@
+-- When each name was first visited, so names can be listed by age. Existing
+-- rows take their earliest recorded visit, falling back to the timestamps
+-- kept on the row for names older than the visit history.
+ALTER TABLE items ADD COLUMN first_seen TIMESTAMPTZ;
+
+UPDATE items SET first_seen = LEAST(
+    (SELECT MIN(visited_at) FROM visits WHERE visits.name = items.name),
+    previous_seen,
+    last_seen
+);
+
+ALTER TABLE items
+    ALTER COLUMN first_seen SET DEFAULT CURRENT_TIMESTAMP,
+    ALTER COLUMN first_seen SET NOT NULL;
@@ -0,0 +1,13 @@
+-- Keys for the routes that are not public. Only a SHA-256 hash of each key
+-- is kept: the key itself is shown once, when it is created. The prefix is
+-- stored in clear so that keys can be told apart in listings.
+CREATE TABLE api_keys (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    prefix TEXT NOT NULL,
+    key_hash BYTEA NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ
+);
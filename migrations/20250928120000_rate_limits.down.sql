@@ -0,0 +1,2 @@
+DROP TABLE counted_visits;
+DROP TABLE rate_limits;
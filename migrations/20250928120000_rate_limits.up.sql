@@ -0,0 +1,16 @@
+-- Token buckets shared by every replica when rate_limit.backend is
+-- postgres, keyed by 'client:<ip>' or 'name:<name>'.
+CREATE TABLE rate_limits (
+    key TEXT PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL
+);
+
+-- Last counted visit of each client to each name, for the duplicate visit
+-- window.
+CREATE TABLE counted_visits (
+    client_ip TEXT NOT NULL,
+    name TEXT NOT NULL,
+    counted_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (client_ip, name)
+);
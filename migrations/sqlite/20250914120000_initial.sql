@@ -0,0 +1,21 @@
+-- Schema of the SQLite backend, matching the Postgres one minus aliases.
+-- Timestamps are microseconds since the Unix epoch, which compare and sort
+-- as plain integers.
+CREATE TABLE items (
+    name TEXT PRIMARY KEY NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    previous_seen INTEGER
+);
+
+CREATE TABLE visits (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    visited_at INTEGER NOT NULL,
+    client_ip TEXT,
+    user_agent TEXT,
+    referer TEXT
+);
+
+CREATE INDEX visits_name_visited_at_idx ON visits (name, visited_at);
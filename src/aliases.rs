@@ -0,0 +1,169 @@
+use sqlx::{PgConnection, PgExecutor, PgPool};
+
+use crate::error::AppError;
+use crate::normalize::Normalizer;
+
+/// Canonical name that visits to `name` are counted under.
+pub async fn resolve(db: impl PgExecutor<'_>, name: &str) -> Result<String, sqlx::Error> {
+    let canonical = sqlx::query_scalar!("SELECT name FROM aliases WHERE alias = $1", name)
+        .fetch_optional(db)
+        .await?;
+    Ok(canonical.unwrap_or_else(|| name.to_string()))
+}
+
+/// Fold the `items` row and visit history of `from` into `into`, summing the
+/// counts and keeping the earliest first_seen and latest last_seen. Returns
+/// the number of visits moved, or `None` when `from` has no row.
+async fn fold_into(
+    conn: &mut PgConnection,
+    from: &str,
+    into: &str,
+) -> Result<Option<i32>, sqlx::Error> {
+    // previous_seen becomes the second most recent of the four timestamps,
+    // which is the later of the smaller last_seen and both previous_seen.
+    let moved = sqlx::query_scalar!(
+        r#"WITH removed AS (
+               DELETE FROM items WHERE name = $1
+               RETURNING count, first_seen, last_seen, previous_seen
+           ),
+           merged AS (
+               INSERT INTO items (name, count, first_seen, last_seen, previous_seen)
+               SELECT $2, count, first_seen, last_seen, previous_seen FROM removed
+               ON CONFLICT (name) DO UPDATE SET
+               count = items.count + EXCLUDED.count,
+               first_seen = LEAST(items.first_seen, EXCLUDED.first_seen),
+               previous_seen = GREATEST(
+                   LEAST(items.last_seen, EXCLUDED.last_seen),
+                   items.previous_seen,
+                   EXCLUDED.previous_seen
+               ),
+               last_seen = GREATEST(items.last_seen, EXCLUDED.last_seen)
+           )
+           SELECT count FROM removed"#,
+        from,
+        into
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    sqlx::query!("UPDATE visits SET name = $2 WHERE name = $1", from, into)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(moved)
+}
+
+/// Record `from` as an alias of `to`, which has to be canonical already.
+async fn link(conn: &mut PgConnection, from: &str, to: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO aliases (alias, name) VALUES ($1, $2)
+         ON CONFLICT (alias) DO UPDATE SET name = EXCLUDED.name",
+        from,
+        to
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // Aliases that pointed at `from` now have to follow it to `to`.
+    sqlx::query!("UPDATE aliases SET name = $2 WHERE name = $1", from, to)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Fold `from` into `into` and keep it as an alias, so that later visits to
+/// `from` are counted under `into` as well.
+pub async fn merge_names(
+    db: &PgPool,
+    normalizer: &Normalizer,
+    from: &str,
+    into: &str,
+) -> Result<(), AppError> {
+    let from = normalizer.normalize(from);
+    let into = resolve(db, &normalizer.normalize(into)).await?;
+    if from == into {
+        return Err(AppError::Invalid(format!(
+            "Cannot merge '{from}' into itself"
+        )));
+    }
+
+    let mut tx = db.begin().await?;
+    let moved = fold_into(&mut tx, &from, &into).await?;
+    link(&mut tx, &from, &into).await?;
+    tx.commit().await?;
+
+    match moved {
+        Some(count) => println!("Merged {count} visits of '{from}' into '{into}'"),
+        None => println!("No visits recorded for '{from}', nothing to merge"),
+    }
+    println!("'{from}' is now an alias of '{into}'");
+    Ok(())
+}
+
+pub async fn add_alias(
+    db: &PgPool,
+    normalizer: &Normalizer,
+    from: &str,
+    to: &str,
+) -> Result<(), AppError> {
+    let from = normalizer.normalize(from);
+    // Point at the end of any existing chain so that lookups stay one hop.
+    let to = resolve(db, &normalizer.normalize(to)).await?;
+    if from == to {
+        return Err(AppError::Invalid(format!(
+            "Cannot alias '{from}' to itself"
+        )));
+    }
+
+    let mut tx = db.begin().await?;
+    link(&mut tx, &from, &to).await?;
+    let moved = fold_into(&mut tx, &from, &to).await?;
+    tx.commit().await?;
+
+    println!("'{from}' is now an alias of '{to}'");
+    if let Some(count) = moved {
+        println!("Merged {count} existing visits of '{from}' into '{to}'");
+    }
+    Ok(())
+}
+
+pub async fn remove_alias(
+    db: &PgPool,
+    normalizer: &Normalizer,
+    from: &str,
+) -> Result<(), AppError> {
+    let from = normalizer.normalize(from);
+
+    let removed = sqlx::query!("DELETE FROM aliases WHERE alias = $1", from)
+        .execute(db)
+        .await?
+        .rows_affected();
+
+    if removed == 0 {
+        println!("'{from}' is not an alias");
+    } else {
+        println!("Removed alias '{from}'");
+    }
+    Ok(())
+}
+
+pub async fn list_aliases(db: &PgPool) -> Result<(), AppError> {
+    let rows = sqlx::query!("SELECT alias, name FROM aliases ORDER BY name, alias")
+        .fetch_all(db)
+        .await?;
+
+    if rows.is_empty() {
+        println!("No aliases defined");
+        return Ok(());
+    }
+
+    println!("{:<20} Name", "Alias");
+    println!("{}", "-".repeat(40));
+
+    for row in rows {
+        println!("{:<20} {}", row.alias, row.name);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,282 @@
+use std::fmt;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+use crate::config::AuthConfig;
+use crate::error::AppError;
+use crate::store::{NameStore, Store};
+
+/// Marks keys in logs and configuration files, ahead of the random part.
+const KEY_PREFIX: &str = "blort_";
+
+/// Characters of a key kept in clear, enough to tell keys apart in listings.
+const DISPLAY_PREFIX_LENGTH: usize = 12;
+
+/// What an API key allows. Each route requires one scope, and scopes do not
+/// imply each other: an `admin` key cannot export unless also given `export`.
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Count visits through `/hello/{name}` and `/api/v1/hello/{name}`
+    Visit,
+    /// Read names and their statistics
+    Read,
+    /// Scrape `/metrics`
+    Metrics,
+    /// Download every name and visit from `/export`
+    Export,
+    /// Delete names through `DELETE /names/{name}`
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Visit => "visit",
+            Scope::Read => "read",
+            Scope::Metrics => "metrics",
+            Scope::Export => "export",
+            Scope::Admin => "admin",
+        }
+    }
+
+    fn parse(scope: &str) -> Option<Scope> {
+        Scope::value_variants()
+            .iter()
+            .copied()
+            .find(|variant| variant.as_str() == scope)
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Parse a comma separated list of scopes.
+pub fn parse_scopes(scopes: &str) -> Result<Vec<Scope>, AppError> {
+    scopes
+        .split(',')
+        .map(str::trim)
+        .filter(|scope| !scope.is_empty())
+        .map(|scope| {
+            Scope::parse(scope).ok_or_else(|| {
+                AppError::Config(format!(
+                    "Unknown scope '{scope}' (expected visit, read, metrics, export or admin)"
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Which scopes are open to every client. Routes of any other scope need a
+/// key granting it.
+#[derive(Clone)]
+pub struct AuthPolicy {
+    public: Vec<Scope>,
+}
+
+impl AuthPolicy {
+    pub fn new(config: &AuthConfig) -> Result<Self, AppError> {
+        Ok(AuthPolicy {
+            public: parse_scopes(&config.public_scopes)?,
+        })
+    }
+
+    pub fn is_public(&self, scope: Scope) -> bool {
+        self.public.contains(&scope)
+    }
+}
+
+/// SHA-256 of the whole key. Keys are long random strings, so a fast
+/// unsalted hash is enough to make a leaked table useless.
+fn hash(key: &str) -> Vec<u8> {
+    Sha256::digest(key.as_bytes()).to_vec()
+}
+
+/// The key presented with a request, as `Authorization: Bearer <key>` or in
+/// an `X-API-Key` header.
+fn presented_key(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let api_key = headers
+        .get(HeaderName::from_static("x-api-key"))
+        .and_then(|value| value.to_str().ok());
+
+    bearer
+        .or(api_key)
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+/// A stored key. Only its prefix is known, the store keeps its hash.
+#[derive(Clone)]
+pub struct KeyRecord {
+    pub id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Scopes of `key` if it exists and was not revoked, recording its use.
+async fn verify(store: &dyn NameStore, key: &str) -> Result<Option<Vec<Scope>>, AppError> {
+    let scopes = store.use_key(&hash(key)).await?;
+
+    // Scopes dropped in a later version are ignored rather than failing.
+    Ok(scopes.map(|scopes| {
+        scopes
+            .iter()
+            .filter_map(|scope| Scope::parse(scope))
+            .collect()
+    }))
+}
+
+/// State of [`authorize`]: the scope one group of routes requires.
+#[derive(Clone)]
+pub struct Guard {
+    store: Store,
+    policy: AuthPolicy,
+    scope: Scope,
+}
+
+impl Guard {
+    pub fn new(store: Store, policy: AuthPolicy, scope: Scope) -> Self {
+        Guard {
+            store,
+            policy,
+            scope,
+        }
+    }
+}
+
+/// Let the request through if its scope is public or its key grants it.
+///
+/// A missing or unknown key is answered with 401, a valid key without the
+/// scope with 403.
+pub async fn authorize(
+    State(guard): State<Guard>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if guard.policy.is_public(guard.scope) {
+        return Ok(next.run(request).await);
+    }
+
+    let key = presented_key(request.headers())
+        .ok_or_else(|| AppError::Unauthorized("An API key is required".to_string()))?;
+    let scopes = verify(&*guard.store, key)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("Unknown or revoked API key".to_string()))?;
+
+    if !scopes.contains(&guard.scope) {
+        return Err(AppError::Forbidden(format!(
+            "This API key lacks the '{}' scope",
+            guard.scope
+        )));
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// A key just created. `key` is only known at this point, the database
+/// keeps its hash.
+pub struct NewKey {
+    pub id: i64,
+    pub key: String,
+}
+
+/// Generate a key granting `scopes` and store its hash under `name`.
+pub async fn insert_key(
+    store: &dyn NameStore,
+    name: &str,
+    scopes: &[Scope],
+) -> Result<NewKey, AppError> {
+    let mut secret = [0u8; 24];
+    OsRng.fill_bytes(&mut secret);
+    let key = format!("{KEY_PREFIX}{}", hex::encode(secret));
+
+    let scopes: Vec<&str> = scopes.iter().map(|scope| scope.as_str()).collect();
+    let id = store
+        .insert_key(name, &key[..DISPLAY_PREFIX_LENGTH], &hash(&key), &scopes)
+        .await?;
+
+    Ok(NewKey { id, key })
+}
+
+pub async fn create_key(
+    store: &dyn NameStore,
+    name: &str,
+    scopes: &[Scope],
+) -> Result<(), AppError> {
+    if scopes.is_empty() {
+        return Err(AppError::Invalid(
+            "A key needs at least one --scope".to_string(),
+        ));
+    }
+
+    let created = insert_key(store, name, scopes).await?;
+    println!("Created key {} '{name}'", created.id);
+    println!("{}", created.key);
+    eprintln!("Store it now, it cannot be shown again");
+    Ok(())
+}
+
+pub async fn list_keys(store: &dyn NameStore) -> Result<(), AppError> {
+    let rows = store.list_keys().await?;
+
+    if rows.is_empty() {
+        println!("No API keys defined");
+        return Ok(());
+    }
+
+    let format_time = |time: Option<DateTime<Utc>>| {
+        time.map_or_else(
+            || "-".to_string(),
+            |time| time.format("%Y-%m-%d %H:%M").to_string(),
+        )
+    };
+
+    println!(
+        "{:<6} {:<20} {:<14} {:<28} {:<17} {:<17} Revoked",
+        "Id", "Name", "Prefix", "Scopes", "Created", "Last used"
+    );
+    println!("{}", "-".repeat(118));
+
+    for row in rows {
+        println!(
+            "{:<6} {:<20} {:<14} {:<28} {:<17} {:<17} {}",
+            row.id,
+            row.name,
+            format!("{}...", row.prefix),
+            row.scopes.join(","),
+            format_time(Some(row.created_at)),
+            format_time(row.last_used_at),
+            format_time(row.revoked_at)
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn revoke_key(store: &dyn NameStore, id: i64) -> Result<(), AppError> {
+    if store.revoke_key(id).await? {
+        println!("Revoked key {id}");
+    } else {
+        println!("No active key with id {id}");
+    }
+    Ok(())
+}
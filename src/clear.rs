@@ -0,0 +1,114 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, IsTerminal, Write};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+
+use crate::error::AppError;
+use crate::store::{Dump, NameStore};
+use crate::transfer::{Format, Record, RecordWriter};
+
+/// Which names `blort clear` removes. Every filter that is set must match;
+/// with none set, everything is removed.
+pub struct ClearFilter {
+    /// Canonical name, already normalized and resolved.
+    pub name: Option<String>,
+    /// Only names last seen before this instant.
+    pub seen_before: Option<DateTime<Utc>>,
+    /// Only names visited at most this many times.
+    pub max_count: Option<i32>,
+}
+
+impl ClearFilter {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.seen_before.is_none() && self.max_count.is_none()
+    }
+}
+
+/// Ask on the terminal whether to go ahead. Without a terminal to ask on,
+/// the caller has to pass `--yes`.
+fn confirm(names: i64, visits: i64) -> Result<bool, AppError> {
+    if !io::stdin().is_terminal() {
+        return Err(AppError::Invalid(
+            "Refusing to delete data without confirmation, pass --yes".to_string(),
+        ));
+    }
+
+    print!("Delete {names} names and {visits} visits? [y/N] ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+impl<W: Write + Send> Dump for RecordWriter<W> {
+    fn write(&mut self, record: Record) -> Result<(), AppError> {
+        RecordWriter::write(self, record)
+    }
+
+    fn finish(&mut self) -> Result<(), AppError> {
+        RecordWriter::finish(self)
+    }
+}
+
+/// A file receiving the removed rows as NDJSON, in the format read by
+/// `blort import`.
+fn create_dump(path: &Path) -> Result<RecordWriter<BufWriter<File>>, AppError> {
+    // Never overwrite an earlier backup.
+    let file = File::create_new(path).map_err(|e| {
+        AppError::Invalid(format!("Cannot create dump file '{}': {e}", path.display()))
+    })?;
+    Ok(RecordWriter::new(Format::Ndjson, BufWriter::new(file)))
+}
+
+/// Delete the names matching `filter` together with their visits.
+///
+/// Unless `assume_yes` is set, the matching rows are counted and the user is
+/// asked to confirm first. With a `dump` path, the deleted rows are streamed
+/// to it before the deletion is committed, and nothing is deleted if that
+/// fails. The dump is removed again when the deletion fails.
+pub async fn clear(
+    store: &dyn NameStore,
+    filter: &ClearFilter,
+    dump: Option<&Path>,
+    assume_yes: bool,
+) -> Result<(), AppError> {
+    if !assume_yes {
+        let (names, visits) = store.count(filter).await?;
+        if names == 0 && visits == 0 {
+            println!("Nothing to delete");
+            return Ok(());
+        }
+        if !confirm(names, visits)? {
+            println!("Aborted, nothing deleted");
+            return Ok(());
+        }
+    }
+
+    let mut dump_file = dump.map(create_dump).transpose()?;
+    let cleared = match store
+        .clear(filter, dump_file.as_mut().map(|file| file as &mut dyn Dump))
+        .await
+    {
+        Ok(cleared) => cleared,
+        Err(e) => {
+            // Nothing was deleted, so the partial dump is of no use and would
+            // only make the retry fail.
+            drop(dump_file);
+            if let Some(path) = dump {
+                let _ = std::fs::remove_file(path);
+            }
+            return Err(e);
+        }
+    };
+
+    println!(
+        "Removed {} names and {} visits",
+        cleared.names, cleared.visits
+    );
+    if let Some(path) = dump {
+        println!("Deleted rows saved to {}", path.display());
+    }
+    Ok(())
+}
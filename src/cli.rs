@@ -0,0 +1,362 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand};
+
+use crate::auth::{self, Scope};
+use crate::clear::{self, ClearFilter};
+use crate::config::Config;
+use crate::error::AppError;
+use crate::names::{self, Direction, NameFilter, OrderBy, Output, Sort};
+use crate::normalize::Normalizer;
+use crate::server::run_server;
+use crate::stats::{self, Granularity};
+use crate::store::{self, require_postgres};
+use crate::validate::NamePolicy;
+use crate::{aliases, migrate, transfer};
+
+#[derive(Parser)]
+#[command(name = "blort")]
+#[command(about = "A name tracking web application")]
+pub struct Cli {
+    /// Configuration file (default: $BLORT_CONFIG, or blort.toml if present)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Database URL (postgres://, sqlite: or memory://), overriding DATABASE_URL
+    #[arg(long, global = true)]
+    pub db_url: Option<String>,
+    /// Do not apply pending migrations, only check that the schema is current
+    #[arg(long, global = true)]
+    pub no_migrate: bool,
+    #[command(subcommand)]
+    pub command: Commands,
+}
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Run the web server
+    Run {
+        /// Address to listen on, e.g. 127.0.0.1:8080 (default: 0.0.0.0:3001)
+        #[arg(long)]
+        bind: Option<SocketAddr>,
+    },
+    /// Delete names and their visits, all of them unless filtered
+    Clear {
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+        /// Only delete this name
+        #[arg(long)]
+        name: Option<String>,
+        /// Only delete names not seen for this long (90m, 12h, 30d, 2w)
+        #[arg(long, value_parser = stats::parse_age)]
+        older_than: Option<chrono::Duration>,
+        /// Only delete names visited at most this many times
+        #[arg(long, value_parser = clap::value_parser!(i32).range(0..))]
+        max_count: Option<i32>,
+        /// Save the deleted rows to this new file, as NDJSON, before deleting
+        #[arg(long)]
+        dump: Option<PathBuf>,
+    },
+    /// Show recent names with their statistics
+    Show {
+        /// Number of names to show (default: 10)
+        #[arg(short, long, default_value_t = 10)]
+        limit: u32,
+        /// Sort key; ties are broken by name
+        #[arg(short, long, value_enum, default_value_t = OrderBy::LastSeen)]
+        order: OrderBy,
+        /// Sort direction (default: ascending for name, descending otherwise)
+        #[arg(short, long, value_enum)]
+        direction: Option<Direction>,
+        /// Time zone to display last_seen in, e.g. Europe/Paris (default: UTC)
+        #[arg(long)]
+        timezone: Option<Tz>,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = Output::Table)]
+        output: Output,
+        /// Only names seen since, as an age (90m, 12h, 30d, 2w) or an RFC 3339 timestamp
+        #[arg(long)]
+        since: Option<String>,
+        /// Only names visited at least this many times
+        #[arg(long)]
+        min_visits: Option<i32>,
+        /// Only names starting with this prefix
+        #[arg(long)]
+        name_prefix: Option<String>,
+    },
+    /// Show visit counts for a name over time
+    Stats {
+        /// Name to report on
+        name: String,
+        /// Size of each bucket
+        #[arg(short, long, value_enum, default_value_t = Granularity::Day)]
+        granularity: Granularity,
+        /// Start of the range, as an age (90m, 12h, 30d, 2w) or an RFC 3339 timestamp
+        #[arg(short, long, default_value = "7d")]
+        since: String,
+    },
+    /// Manage alternative spellings of names
+    Alias {
+        #[command(subcommand)]
+        command: AliasCommand,
+    },
+    /// Fold the visits of one name into another, keeping it as an alias
+    Merge {
+        /// Name to merge away
+        from: String,
+        /// Name that receives the visits
+        into: String,
+    },
+    /// Write all names and visits to a file or stdout
+    Export {
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = transfer::Format::Ndjson)]
+        format: transfer::Format,
+        /// File to write to (default: stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Load names and visits written by `export`
+    Import {
+        /// File to read, or - for stdin
+        file: PathBuf,
+        /// Input format (default: guessed from the file extension)
+        #[arg(short, long, value_enum)]
+        format: Option<transfer::Format>,
+        /// Whether to add to or replace the existing data
+        #[arg(short, long, value_enum, default_value_t = transfer::ImportMode::Merge)]
+        mode: transfer::ImportMode,
+    },
+    /// Manage the API keys protecting non-public routes
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommand,
+    },
+    /// Manage the database schema
+    Migrate {
+        #[command(subcommand)]
+        command: MigrateCommand,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AliasCommand {
+    /// Count future visits of <FROM> as <TO>, merging its existing visits
+    Add { from: String, to: String },
+    /// Stop redirecting visits of an alias
+    Remove { from: String },
+    /// List all aliases
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum KeysCommand {
+    /// Generate a key and print it, once
+    Create {
+        /// What the key is for, shown in listings
+        #[arg(long)]
+        name: String,
+        /// Scope granted to the key; repeat for several
+        #[arg(long = "scope", value_enum, required = true)]
+        scopes: Vec<Scope>,
+    },
+    /// List keys, without their secret part
+    List,
+    /// Stop accepting a key
+    Revoke {
+        /// Id shown by `keys list`
+        id: i64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum MigrateCommand {
+    /// Apply all pending migrations
+    Up,
+    /// List migrations and whether they are applied
+    Status,
+    /// Revert the latest migration, or every migration after --target
+    Revert {
+        /// Version to revert down to, 0 to revert everything
+        #[arg(long)]
+        target: Option<i64>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective configuration as TOML, with secrets redacted
+    Print,
+}
+
+/// Layer the command line flags over the file and environment configuration.
+pub fn load_config(cli: &Cli) -> Result<Config, AppError> {
+    let mut config = Config::load(cli.config.as_deref())?;
+
+    if let Some(url) = &cli.db_url {
+        config.database_url = Some(url.clone());
+    }
+    if cli.no_migrate {
+        config.database.migrate_on_start = false;
+    }
+    if let Commands::Run { bind: Some(bind) } = cli.command {
+        config.bind = bind;
+    }
+
+    config.validate()?;
+    Ok(config)
+}
+
+pub async fn run(cli: Cli, config: Config) -> Result<(), AppError> {
+    if let Commands::Config { command } = &cli.command {
+        match command {
+            ConfigCommand::Print => print!("{}", config.to_redacted_toml()?),
+        }
+        return Ok(());
+    }
+
+    let store = store::open(config.database_url()?, &config.database).await?;
+
+    if let Commands::Migrate { command } = &cli.command {
+        let db = require_postgres(&*store, "Managing migrations")?;
+        return match command {
+            MigrateCommand::Up => migrate::up(db).await,
+            MigrateCommand::Status => migrate::status(db).await,
+            MigrateCommand::Revert { target } => migrate::revert(db, *target).await,
+        };
+    }
+
+    if let Some(db) = store.postgres() {
+        if config.database.migrate_on_start {
+            migrate::MIGRATOR.run(db).await?;
+            tracing::debug!("Database connected and migrated");
+        } else {
+            migrate::ensure_current(db).await?;
+            tracing::debug!("Database connected, schema is current");
+        }
+    }
+
+    let normalizer = Normalizer::from_steps(&config.names.normalization)?;
+
+    match cli.command {
+        Commands::Run { .. } => {
+            let policy = NamePolicy::new(&config.names, &normalizer)?;
+            run_server(store, &config, normalizer, policy).await?
+        }
+        Commands::Clear {
+            yes,
+            name,
+            older_than,
+            max_count,
+            dump,
+        } => {
+            let name = match name {
+                Some(name) => Some(store.resolve(&normalizer.normalize(&name)).await?),
+                None => None,
+            };
+            let filter = ClearFilter {
+                name,
+                seen_before: older_than
+                    .map(|age| stats::ago(chrono::Utc::now(), age))
+                    .transpose()
+                    .map_err(AppError::Invalid)?,
+                max_count,
+            };
+            clear::clear(&*store, &filter, dump.as_deref(), yes).await?
+        }
+        Commands::Show {
+            limit,
+            order,
+            direction,
+            timezone,
+            output,
+            since,
+            min_visits,
+            name_prefix,
+        } => {
+            let filter = NameFilter::parse(
+                since.as_deref(),
+                min_visits,
+                name_prefix.as_deref(),
+                &normalizer,
+            )?;
+            let tz = timezone.unwrap_or(Tz::UTC);
+            let sort = Sort::new(order, direction);
+            names::show_names(&*store, sort, &filter, limit, output, tz).await?
+        }
+        Commands::Stats {
+            name,
+            granularity,
+            since,
+        } => {
+            let name = store.resolve(&normalizer.normalize(&name)).await?;
+            stats::show_stats(&*store, name, granularity, &since).await?
+        }
+        Commands::Alias { command } => {
+            let db = require_postgres(&*store, "Aliases")?;
+            match command {
+                AliasCommand::Add { from, to } => {
+                    aliases::add_alias(db, &normalizer, &from, &to).await?
+                }
+                AliasCommand::Remove { from } => {
+                    aliases::remove_alias(db, &normalizer, &from).await?
+                }
+                AliasCommand::List => aliases::list_aliases(db).await?,
+            }
+        }
+        Commands::Merge { from, into } => {
+            let db = require_postgres(&*store, "Merging names")?;
+            aliases::merge_names(db, &normalizer, &from, &into).await?
+        }
+        Commands::Export { format, output } => {
+            let db = require_postgres(&*store, "Exporting")?;
+            transfer::export(db, format, output.as_deref()).await?
+        }
+        Commands::Import { file, format, mode } => {
+            let db = require_postgres(&*store, "Importing")?;
+            transfer::import(db, &file, format, mode).await?
+        }
+        Commands::Keys { command } => match command {
+            KeysCommand::Create { name, scopes } => {
+                auth::create_key(&*store, &name, &scopes).await?
+            }
+            KeysCommand::List => auth::list_keys(&*store).await?,
+            KeysCommand::Revoke { id } => auth::revoke_key(&*store, id).await?,
+        },
+        Commands::Migrate { .. } | Commands::Config { .. } => {
+            unreachable!("handled before migrating")
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_override_the_configuration() {
+        let cli = Cli::parse_from([
+            "blort",
+            "--db-url",
+            "sqlite::memory:",
+            "--no-migrate",
+            "run",
+            "--bind",
+            "127.0.0.1:4000",
+        ]);
+        let config = load_config(&cli).unwrap();
+
+        assert_eq!(config.database_url.as_deref(), Some("sqlite::memory:"));
+        assert_eq!(config.bind, "127.0.0.1:4000".parse().unwrap());
+        assert!(!config.database.migrate_on_start);
+    }
+}
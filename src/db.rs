@@ -0,0 +1,88 @@
+use std::io;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{ConnectOptions, Connection, PgPool};
+
+use crate::config::DatabaseConfig;
+use crate::error::AppError;
+
+/// Wait before the second connection attempt, doubled after each failure.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+/// Upper bound on the wait between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+fn pool_options(config: &DatabaseConfig) -> PgPoolOptions {
+    let idle_timeout =
+        (config.idle_timeout_secs > 0).then(|| Duration::from_secs(config.idle_timeout_secs));
+
+    PgPoolOptions::new()
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout_secs))
+        .idle_timeout(idle_timeout)
+}
+
+fn connect_options(url: &str, config: &DatabaseConfig) -> Result<PgConnectOptions, AppError> {
+    let options = PgConnectOptions::from_str(url)
+        .map_err(|e| AppError::Config(format!("Invalid database URL: {e}")))?;
+
+    // Sent as a startup parameter, so it applies to every pooled connection.
+    Ok(if config.statement_timeout_ms > 0 {
+        options.options([("statement_timeout", config.statement_timeout_ms.to_string())])
+    } else {
+        options
+    })
+}
+
+/// Open the connection pool, retrying with exponential backoff while
+/// Postgres is unreachable, e.g. when both are started together by
+/// docker-compose. Gives up after `startup_timeout_secs`.
+///
+/// Errors that retrying cannot fix, such as bad credentials or an unknown
+/// database, are returned straight away.
+pub async fn connect(url: &str, config: &DatabaseConfig) -> Result<PgPool, AppError> {
+    let options = connect_options(url, config)?;
+    let acquire_timeout = Duration::from_secs(config.acquire_timeout_secs);
+    let deadline = Instant::now() + Duration::from_secs(config.startup_timeout_secs);
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+        // A single plain connection reports the actual failure, where the
+        // pool would keep retrying internally until its acquire timeout.
+        let error = match tokio::time::timeout(acquire_timeout, options.connect()).await {
+            Ok(Ok(conn)) => {
+                tracing::debug!(attempt, "Connected to the database");
+                conn.close().await?;
+                return Ok(pool_options(config).connect_with(options).await?);
+            }
+            Ok(Err(e)) => AppError::from(e),
+            Err(_) => AppError::from(sqlx::Error::Io(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("no answer within {acquire_timeout:?}"),
+            ))),
+        };
+
+        if !matches!(error, AppError::Unavailable(_)) {
+            return Err(error);
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            tracing::error!(attempt, "Database still unavailable, giving up");
+            return Err(error);
+        }
+
+        let wait = backoff.min(remaining);
+        tracing::warn!(
+            attempt,
+            error = %error,
+            retry_in = ?wait,
+            "Database unavailable, retrying"
+        );
+        tokio::time::sleep(wait).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,223 @@
+use std::fmt;
+use std::time::Duration;
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::validate::NameRejection;
+
+/// Errors shared by the HTTP handlers and the CLI commands.
+///
+/// Over HTTP only a short, generic description is sent to the client; the
+/// full error is logged server-side.
+#[derive(Debug)]
+pub enum AppError {
+    /// Postgres could not be reached or the pool is exhausted.
+    Unavailable(sqlx::Error),
+    /// A query violated a database constraint.
+    Constraint(sqlx::Error),
+    /// Any other database failure.
+    Database(sqlx::Error),
+    Migrate(sqlx::migrate::MigrateError),
+    /// The database schema does not match what this binary expects.
+    Schema(String),
+    /// The caller supplied an invalid parameter or argument.
+    Invalid(String),
+    /// No valid API key was presented for a protected route.
+    Unauthorized(String),
+    /// The API key lacks the scope the route requires.
+    Forbidden(String),
+    /// The requested name or resource does not exist.
+    NotFound(String),
+    /// The client or name used up its visits, until this much time has passed.
+    RateLimited(Duration),
+    /// The feature is not available with the configured storage backend.
+    Unsupported(String),
+    InvalidName(NameRejection),
+    /// The environment or configuration is unusable.
+    Config(String),
+    Io(std::io::Error),
+}
+
+#[derive(Serialize)]
+pub struct ErrorBody {
+    pub error: &'static str,
+    pub message: String,
+}
+
+impl AppError {
+    /// Whether a database error means Postgres is unreachable rather than
+    /// that the query itself failed.
+    fn is_unavailable(error: &sqlx::Error) -> bool {
+        match error {
+            sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::WorkerCrashed => true,
+            // Class 08 is connection exceptions, 53 insufficient resources
+            // and 57P0x the server shutting down or refusing connections.
+            sqlx::Error::Database(db_error) => db_error.code().is_some_and(|code| {
+                code.starts_with("08") || code.starts_with("53") || code.starts_with("57P0")
+            }),
+            _ => false,
+        }
+    }
+}
+
+/// Whole seconds for `Retry-After`, rounded up so that clients never retry
+/// too early.
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(error: sqlx::Error) -> Self {
+        if Self::is_unavailable(&error) {
+            return AppError::Unavailable(error);
+        }
+        match &error {
+            sqlx::Error::Database(db_error)
+                if db_error.is_unique_violation()
+                    || db_error.is_foreign_key_violation()
+                    || db_error.is_check_violation() =>
+            {
+                AppError::Constraint(error)
+            }
+            _ => AppError::Database(error),
+        }
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for AppError {
+    fn from(error: sqlx::migrate::MigrateError) -> Self {
+        AppError::Migrate(error)
+    }
+}
+
+impl From<NameRejection> for AppError {
+    fn from(rejection: NameRejection) -> Self {
+        AppError::InvalidName(rejection)
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(error: std::io::Error) -> Self {
+        AppError::Io(error)
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Unavailable(e) => write!(f, "Database unavailable: {e}"),
+            AppError::Constraint(e) => write!(f, "Constraint violation: {e}"),
+            AppError::Database(e) => write!(f, "Database error: {e}"),
+            AppError::Migrate(e) => write!(f, "Migration error: {e}"),
+            AppError::Schema(message) => write!(f, "Schema error: {message}"),
+            AppError::Invalid(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::NotFound(message)
+            | AppError::Unsupported(message) => f.write_str(message),
+            AppError::RateLimited(retry_after) => write!(
+                f,
+                "Too many visits, retry in {} seconds",
+                retry_after_secs(*retry_after)
+            ),
+            AppError::InvalidName(rejection) => write!(f, "Invalid name: {rejection}"),
+            AppError::Config(message) => write!(f, "Configuration error: {message}"),
+            AppError::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AppError::Unavailable(e) | AppError::Constraint(e) | AppError::Database(e) => Some(e),
+            AppError::Migrate(e) => Some(e),
+            AppError::Io(e) => Some(e),
+            AppError::Schema(_)
+            | AppError::Invalid(_)
+            | AppError::Unauthorized(_)
+            | AppError::Forbidden(_)
+            | AppError::NotFound(_)
+            | AppError::RateLimited(_)
+            | AppError::Unsupported(_)
+            | AppError::InvalidName(_)
+            | AppError::Config(_) => None,
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, error, message) = match &self {
+            AppError::InvalidName(rejection) => {
+                (rejection.status(), rejection.code(), rejection.to_string())
+            }
+            AppError::Invalid(message) => {
+                (StatusCode::BAD_REQUEST, "invalid_request", message.clone())
+            }
+            AppError::Unauthorized(message) => {
+                (StatusCode::UNAUTHORIZED, "unauthorized", message.clone())
+            }
+            AppError::Forbidden(message) => (StatusCode::FORBIDDEN, "forbidden", message.clone()),
+            AppError::NotFound(message) => (StatusCode::NOT_FOUND, "not_found", message.clone()),
+            AppError::RateLimited(_) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+                self.to_string(),
+            ),
+            AppError::Unsupported(message) => (
+                StatusCode::NOT_IMPLEMENTED,
+                "not_implemented",
+                message.clone(),
+            ),
+            AppError::Constraint(_) => (
+                StatusCode::CONFLICT,
+                "conflict",
+                "The request conflicts with existing data".to_string(),
+            ),
+            AppError::Unavailable(_) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "unavailable",
+                "The service is temporarily unavailable".to_string(),
+            ),
+            AppError::Database(_)
+            | AppError::Migrate(_)
+            | AppError::Schema(_)
+            | AppError::Config(_)
+            | AppError::Io(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal_error",
+                "An internal error occurred".to_string(),
+            ),
+        };
+
+        if status.is_server_error() {
+            tracing::error!(error = %self, "request failed");
+        }
+
+        let mut response = (status, Json(ErrorBody { error, message })).into_response();
+        let headers = response.headers_mut();
+        match &self {
+            AppError::Unauthorized(_) => {
+                headers.insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            AppError::RateLimited(retry_after) => {
+                headers.insert(
+                    header::RETRY_AFTER,
+                    HeaderValue::from(retry_after_secs(*retry_after)),
+                );
+            }
+            _ => {}
+        }
+        response
+    }
+}
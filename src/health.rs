@@ -0,0 +1,143 @@
+use std::time::{Duration, Instant};
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+
+use crate::migrate::{applied_version, expected_version};
+use crate::monitoring;
+use crate::shutdown::Shutdown;
+use crate::store::{NameStore, Store};
+
+/// Upper bound on each readiness check, so that a hung database makes the
+/// probe fail instead of time out.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CheckStatus {
+    Ok,
+    Failing,
+}
+
+#[derive(Serialize)]
+struct CheckResult {
+    status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl CheckResult {
+    fn ok(latency: Duration, detail: Option<String>) -> Self {
+        CheckResult {
+            status: CheckStatus::Ok,
+            latency_ms: Some(latency.as_millis()),
+            detail,
+        }
+    }
+
+    fn failing(detail: String) -> Self {
+        CheckResult {
+            status: CheckStatus::Failing,
+            latency_ms: None,
+            detail: Some(detail),
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        matches!(self.status, CheckStatus::Ok)
+    }
+}
+
+#[derive(Serialize)]
+struct Checks {
+    database: CheckResult,
+    migrations: CheckResult,
+}
+
+#[derive(Serialize)]
+pub struct Readiness {
+    ready: bool,
+    draining: bool,
+    checks: Checks,
+}
+
+async fn check_database(store: &dyn NameStore) -> CheckResult {
+    let started = Instant::now();
+    match tokio::time::timeout(CHECK_TIMEOUT, store.ping()).await {
+        Ok(Ok(_)) => CheckResult::ok(started.elapsed(), None),
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "readiness: database check failed");
+            CheckResult::failing("Query failed".to_string())
+        }
+        Err(_) => CheckResult::failing(format!("No answer within {CHECK_TIMEOUT:?}")),
+    }
+}
+
+async fn check_migrations(store: &dyn NameStore) -> CheckResult {
+    let started = Instant::now();
+    // Other backends migrate their schema whenever they are opened.
+    let Some(db) = store.postgres() else {
+        return CheckResult::ok(started.elapsed(), None);
+    };
+    let expected = expected_version();
+
+    // A schema ahead of the binary is fine: during a rolling deploy the new
+    // version migrates while older instances keep serving.
+    let applied = async {
+        let mut conn = monitoring::acquire(db).await?;
+        applied_version(&mut *conn).await
+    };
+    match tokio::time::timeout(CHECK_TIMEOUT, applied).await {
+        Ok(Ok(applied)) if applied >= expected => {
+            let detail = applied.map(|version| format!("At version {version}"));
+            CheckResult::ok(started.elapsed(), detail)
+        }
+        Ok(Ok(applied)) => CheckResult::failing(format!(
+            "Schema is at version {}, expected {}",
+            applied.unwrap_or_default(),
+            expected.unwrap_or_default()
+        )),
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "readiness: migration check failed");
+            CheckResult::failing("Cannot read the migration history".to_string())
+        }
+        Err(_) => CheckResult::failing(format!("No answer within {CHECK_TIMEOUT:?}")),
+    }
+}
+
+/// Liveness: the process is up and serving requests.
+pub async fn livez() -> &'static str {
+    "OK"
+}
+
+/// Readiness: the instance can actually serve `/hello`, i.e. it is not
+/// shutting down, the database answers and its schema includes every migration
+/// embedded in this binary.
+pub async fn readyz(
+    State(store): State<Store>,
+    State(shutdown): State<Shutdown>,
+) -> (StatusCode, Json<Readiness>) {
+    let (database, migrations) = tokio::join!(check_database(&*store), check_migrations(&*store));
+
+    let draining = shutdown.is_draining();
+    let ready = !draining && database.is_ok() && migrations.is_ok();
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(Readiness {
+            ready,
+            draining,
+            checks: Checks {
+                database,
+                migrations,
+            },
+        }),
+    )
+}
@@ -0,0 +1,31 @@
+//! Name tracking web application.
+//!
+//! The routes are built by [`router`] from an [`AppState`], so they can be
+//! mounted in another axum application. Persistence goes through the
+//! [`store::NameStore`] trait, and every `blort` subcommand is implemented
+//! by a public function of the module it belongs to, dispatched by
+//! [`cli::run`].
+
+pub mod aliases;
+pub mod auth;
+pub mod clear;
+pub mod cli;
+pub mod config;
+pub mod db;
+pub mod error;
+pub mod health;
+pub mod migrate;
+pub mod monitoring;
+pub mod names;
+pub mod normalize;
+pub mod proxies;
+pub mod ratelimit;
+pub mod server;
+pub mod shutdown;
+pub mod stats;
+pub mod store;
+pub mod telemetry;
+pub mod transfer;
+pub mod validate;
+
+pub use server::{record_visit, router, run_server, AppState, HelloResponse};
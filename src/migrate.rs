@@ -0,0 +1,171 @@
+use chrono::{DateTime, Utc};
+use sqlx::migrate::Migrator;
+use sqlx::{PgExecutor, PgPool};
+
+use crate::error::AppError;
+
+/// Migrations embedded in the binary from `migrations/`.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// A row of the `_sqlx_migrations` bookkeeping table.
+#[derive(sqlx::FromRow)]
+struct AppliedMigration {
+    version: i64,
+    description: String,
+    installed_on: DateTime<Utc>,
+    success: bool,
+    checksum: Vec<u8>,
+}
+
+/// Whether an error means the bookkeeping table does not exist yet, which
+/// only happens before migrations have run for the first time.
+fn is_missing_table(error: &sqlx::Error) -> bool {
+    matches!(error, sqlx::Error::Database(db_error) if db_error.code().as_deref() == Some("42P01"))
+}
+
+/// Latest migration applied to the database, or `None` on an empty schema.
+pub async fn applied_version(db: impl PgExecutor<'_>) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+        .fetch_one(db)
+        .await
+        .or_else(|e| {
+            if is_missing_table(&e) {
+                Ok(None)
+            } else {
+                Err(e)
+            }
+        })
+}
+
+/// Latest migration embedded in this binary.
+pub fn expected_version() -> Option<i64> {
+    MIGRATOR.iter().map(|migration| migration.version).max()
+}
+
+async fn applied_migrations(db: &PgPool) -> Result<Vec<AppliedMigration>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT version, description, installed_on, success, checksum
+         FROM _sqlx_migrations
+         ORDER BY version",
+    )
+    .fetch_all(db)
+    .await
+    .or_else(|e| {
+        if is_missing_table(&e) {
+            Ok(Vec::new())
+        } else {
+            Err(e)
+        }
+    })
+}
+
+/// Refuse to go on with a schema older than this binary expects.
+///
+/// Used instead of migrating when migrations are run separately, e.g. by a
+/// deploy step or with a privileged role. A newer schema is accepted, as
+/// during a rolling deploy.
+pub async fn ensure_current(db: &PgPool) -> Result<(), AppError> {
+    let applied = applied_version(db).await?;
+    let expected = expected_version();
+
+    if applied < expected {
+        return Err(AppError::Schema(format!(
+            "The database is at version {}, this binary needs {}; run `blort migrate up`",
+            applied.unwrap_or_default(),
+            expected.unwrap_or_default()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Apply every pending migration.
+pub async fn up(db: &PgPool) -> Result<(), AppError> {
+    let before = applied_version(db).await?;
+    MIGRATOR.run(db).await?;
+
+    let applied: Vec<_> = MIGRATOR
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+        .filter(|migration| Some(migration.version) > before)
+        .collect();
+
+    if applied.is_empty() {
+        println!("Schema is up to date");
+    }
+    for migration in applied {
+        println!("Applied {} {}", migration.version, migration.description);
+    }
+
+    Ok(())
+}
+
+/// List embedded and applied migrations, without changing anything.
+pub async fn status(db: &PgPool) -> Result<(), AppError> {
+    let applied = applied_migrations(db).await?;
+
+    println!("{:<16} {:<24} Status", "Version", "Description");
+    println!("{}", "-".repeat(70));
+
+    for migration in MIGRATOR
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+    {
+        let state = match applied.iter().find(|row| row.version == migration.version) {
+            None => "pending".to_string(),
+            Some(row) if !row.success => "failed".to_string(),
+            Some(row) if *row.checksum != *migration.checksum => {
+                "applied, but differs from this binary".to_string()
+            }
+            Some(row) => format!("applied {}", row.installed_on.format("%Y-%m-%d %H:%M:%S")),
+        };
+        println!(
+            "{:<16} {:<24} {}",
+            migration.version, migration.description, state
+        );
+    }
+
+    // Left behind by a newer binary, or by a migration since removed.
+    for row in applied.iter().filter(|row| {
+        MIGRATOR
+            .iter()
+            .all(|migration| migration.version != row.version)
+    }) {
+        println!(
+            "{:<16} {:<24} applied {}, unknown to this binary",
+            row.version,
+            row.description,
+            row.installed_on.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+
+    Ok(())
+}
+
+/// Revert applied migrations newer than `target`, by default only the latest.
+pub async fn revert(db: &PgPool, target: Option<i64>) -> Result<(), AppError> {
+    let applied = applied_migrations(db).await?;
+    let mut versions = applied
+        .iter()
+        .filter(|row| row.success)
+        .map(|row| row.version)
+        .rev();
+
+    if versions.next().is_none() {
+        println!("No migration to revert");
+        return Ok(());
+    }
+    let target = target.unwrap_or_else(|| versions.next().unwrap_or(0));
+
+    MIGRATOR.undo(db, target).await?;
+
+    for row in applied
+        .iter()
+        .rev()
+        .filter(|row| row.success && row.version > target)
+    {
+        println!("Reverted {} {}", row.version, row.description);
+    }
+
+    Ok(())
+}
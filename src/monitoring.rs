@@ -0,0 +1,121 @@
+use std::sync::Mutex;
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use sqlx::{pool::PoolConnection, PgPool, Postgres, Transaction};
+
+use crate::error::AppError;
+use crate::store::Store;
+
+/// Bucket boundaries, in seconds, shared by every latency histogram.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Handle to the process-wide Prometheus recorder, rendered by `/metrics`.
+#[derive(Clone)]
+pub struct Metrics {
+    handle: PrometheusHandle,
+}
+
+/// Handle to the recorder installed by [`Metrics::install`].
+static RECORDER: Mutex<Option<PrometheusHandle>> = Mutex::new(None);
+
+impl Metrics {
+    /// Install the global metrics recorder, or return the one installed by
+    /// an earlier call, e.g. by another test in the same process.
+    pub fn install() -> Result<Self, AppError> {
+        let mut recorder = RECORDER
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(handle) = &*recorder {
+            return Ok(Metrics {
+                handle: handle.clone(),
+            });
+        }
+
+        let handle = PrometheusBuilder::new()
+            .set_buckets_for_metric(
+                Matcher::Suffix("duration_seconds".to_string()),
+                LATENCY_BUCKETS,
+            )
+            .and_then(|builder| builder.install_recorder())
+            .map_err(|e| AppError::Config(format!("Cannot install metrics recorder: {e}")))?;
+        *recorder = Some(handle.clone());
+
+        Ok(Metrics { handle })
+    }
+}
+
+/// Count and time every request, labelled by its route template rather than
+/// the concrete path so that names do not blow up the label cardinality.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".to_owned());
+    let method = request.method().to_string();
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let elapsed = started.elapsed().as_secs_f64();
+
+    let labels = [
+        ("method", method),
+        ("route", route),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    metrics::counter!("http_requests_total", &labels).increment(1);
+    metrics::histogram!("http_request_duration_seconds", &labels).record(elapsed);
+
+    response
+}
+
+/// Take a connection from the pool, recording how long the wait was. Every
+/// query of the server goes through here, so the histogram shows when the
+/// pool is too small for the load.
+pub async fn acquire(db: &PgPool) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+    let started = Instant::now();
+    let conn = db.acquire().await;
+    metrics::histogram!("blort_db_pool_acquire_duration_seconds")
+        .record(started.elapsed().as_secs_f64());
+    conn
+}
+
+/// Start a transaction on a connection taken by [`acquire`].
+pub async fn begin(db: &PgPool) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    Transaction::begin(acquire(db).await?, None).await
+}
+
+/// Count a failed `/hello` upsert, by error kind.
+pub fn record_upsert_error(error: &AppError) {
+    let kind = match error {
+        AppError::Unavailable(_) => "unavailable",
+        AppError::Constraint(_) => "constraint",
+        _ => "database",
+    };
+    metrics::counter!("blort_upsert_errors_total", "kind" => kind).increment(1);
+}
+
+pub async fn metrics_handler(State(metrics): State<Metrics>, State(store): State<Store>) -> String {
+    // Gauges that describe current state are sampled at scrape time.
+    if let Some(db) = store.postgres() {
+        let idle = db.num_idle() as f64;
+        metrics::gauge!("blort_db_pool_connections", "state" => "idle").set(idle);
+        metrics::gauge!("blort_db_pool_connections", "state" => "active")
+            .set(f64::from(db.size()) - idle);
+    }
+
+    match store.count_names().await {
+        Ok(names) => metrics::gauge!("blort_distinct_names").set(names as f64),
+        Err(e) => tracing::warn!(error = %e, "cannot count distinct names for metrics"),
+    }
+
+    metrics.handle.render()
+}
@@ -0,0 +1,453 @@
+use std::io::{self, Write};
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use unicode_width::UnicodeWidthStr;
+
+use crate::clear::ClearFilter;
+use crate::error::AppError;
+use crate::normalize::Normalizer;
+use crate::stats::parse_time;
+use crate::store::{NameStore, Store};
+use crate::validate::Query;
+
+/// Upper bound on the page size accepted by `GET /names`.
+const MAX_PAGE_SIZE: u32 = 100;
+
+#[derive(FromRow)]
+pub struct NameRecord {
+    pub name: String,
+    pub count: i32,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// A [`NameRecord`] as returned over HTTP, with timestamps in the caller's zone.
+#[derive(Serialize)]
+pub struct NameEntry {
+    name: String,
+    count: i32,
+    first_seen: DateTime<Tz>,
+    last_seen: DateTime<Tz>,
+}
+
+impl NameEntry {
+    fn new(record: NameRecord, tz: Tz) -> Self {
+        NameEntry {
+            name: record.name,
+            count: record.count,
+            first_seen: record.first_seen.with_timezone(&tz),
+            last_seen: record.last_seen.with_timezone(&tz),
+        }
+    }
+}
+
+/// Restricts which names are listed. Every filter that is set must match.
+#[derive(Default)]
+pub struct NameFilter {
+    /// Only names seen at or after this instant.
+    pub seen_since: Option<DateTime<Utc>>,
+    /// Only names visited at least this many times.
+    pub min_visits: Option<i32>,
+    /// Only names starting with this, already normalized.
+    pub name_prefix: Option<String>,
+}
+
+impl NameFilter {
+    /// Build a filter from user input: `since` is an age or an RFC 3339
+    /// timestamp, and `name_prefix` is normalized like names are.
+    pub fn parse(
+        since: Option<&str>,
+        min_visits: Option<i32>,
+        name_prefix: Option<&str>,
+        normalizer: &Normalizer,
+    ) -> Result<Self, AppError> {
+        let seen_since = since
+            .map(|since| parse_time(since, Utc::now()))
+            .transpose()
+            .map_err(AppError::Invalid)?;
+
+        Ok(NameFilter {
+            seen_since,
+            min_visits,
+            name_prefix: name_prefix.map(|prefix| normalizer.normalize(prefix)),
+        })
+    }
+}
+
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderBy {
+    #[default]
+    LastSeen,
+    Visits,
+    Name,
+    FirstSeen,
+}
+
+impl OrderBy {
+    pub fn label(self) -> &'static str {
+        match self {
+            OrderBy::LastSeen => "last seen",
+            OrderBy::Visits => "visits",
+            OrderBy::Name => "name",
+            OrderBy::FirstSeen => "first seen",
+        }
+    }
+
+    /// Column holding the sort key.
+    pub fn column(self) -> &'static str {
+        match self {
+            OrderBy::LastSeen => "last_seen",
+            OrderBy::Visits => "count",
+            OrderBy::Name => "name",
+            OrderBy::FirstSeen => "first_seen",
+        }
+    }
+
+    /// Sort key of `record`, as encoded in a [`Cursor`].
+    pub fn key(self, record: &NameRecord) -> i64 {
+        match self {
+            OrderBy::LastSeen => record.last_seen.timestamp_micros(),
+            OrderBy::FirstSeen => record.first_seen.timestamp_micros(),
+            OrderBy::Visits => i64::from(record.count),
+            OrderBy::Name => 0,
+        }
+    }
+
+    /// Direction used when none is given: most recent or most visited first,
+    /// names alphabetically.
+    pub fn default_direction(self) -> Direction {
+        match self {
+            OrderBy::Name => Direction::Asc,
+            OrderBy::LastSeen | OrderBy::Visits | OrderBy::FirstSeen => Direction::Desc,
+        }
+    }
+}
+
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+impl Direction {
+    pub fn label(self) -> &'static str {
+        match self {
+            Direction::Asc => "ascending",
+            Direction::Desc => "descending",
+        }
+    }
+
+    pub fn as_sql(self) -> &'static str {
+        match self {
+            Direction::Asc => "ASC",
+            Direction::Desc => "DESC",
+        }
+    }
+}
+
+/// Sort key and direction of a listing. Rows with equal keys are ordered by
+/// name, in the same direction, so the order is always total.
+#[derive(Clone, Copy)]
+pub struct Sort {
+    pub order: OrderBy,
+    pub direction: Direction,
+}
+
+impl Sort {
+    pub fn new(order: OrderBy, direction: Option<Direction>) -> Self {
+        Sort {
+            order,
+            direction: direction.unwrap_or_else(|| order.default_direction()),
+        }
+    }
+}
+
+/// Position after the last row of a page, for keyset pagination.
+///
+/// Rows are ordered by the sort key and then by name, so the pair uniquely
+/// identifies a position even when several names share a count or timestamp.
+/// On the wire it is `<key>:<name>`, where the key is the visit count, a
+/// timestamp in microseconds since the epoch, or unused (0) when sorting by
+/// name.
+pub struct Cursor {
+    pub key: i64,
+    pub name: String,
+}
+
+impl Cursor {
+    pub fn after(order: OrderBy, record: &NameRecord) -> Self {
+        Cursor {
+            key: order.key(record),
+            name: record.name.clone(),
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, AppError> {
+        let invalid = || AppError::Invalid(format!("Invalid cursor '{value}'"));
+        let (key, name) = value.split_once(':').ok_or_else(invalid)?;
+        let key = key.parse().map_err(|_| invalid())?;
+        Ok(Cursor {
+            key,
+            name: name.to_string(),
+        })
+    }
+
+    fn encode(&self) -> String {
+        format!("{}:{}", self.key, self.name)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ListQuery {
+    #[serde(default)]
+    order: OrderBy,
+    direction: Option<Direction>,
+    limit: Option<u32>,
+    #[serde(default)]
+    offset: u32,
+    cursor: Option<String>,
+    tz: Option<Tz>,
+    /// Age such as `30d`, or RFC 3339 timestamp.
+    since: Option<String>,
+    min_visits: Option<i32>,
+    name_prefix: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct NamePage {
+    order: OrderBy,
+    direction: Direction,
+    names: Vec<NameEntry>,
+    /// Pass back as `cursor` to fetch the following page; `null` on the last page.
+    next_cursor: Option<String>,
+}
+
+pub async fn list_handler(
+    Query(query): Query<ListQuery>,
+    State(store): State<Store>,
+    State(normalizer): State<Normalizer>,
+) -> Result<Json<NamePage>, AppError> {
+    let limit = query.limit.unwrap_or(20).clamp(1, MAX_PAGE_SIZE);
+    let after = query.cursor.as_deref().map(Cursor::parse).transpose()?;
+    let filter = NameFilter::parse(
+        query.since.as_deref(),
+        query.min_visits,
+        query.name_prefix.as_deref(),
+        &normalizer,
+    )?;
+
+    let sort = Sort::new(query.order, query.direction);
+    let records = store
+        .list(sort, &filter, limit, query.offset, after.as_ref())
+        .await?;
+
+    let next_cursor = match records.last() {
+        Some(last) if records.len() == limit as usize => {
+            Some(Cursor::after(sort.order, last).encode())
+        }
+        _ => None,
+    };
+
+    let tz = query.tz.unwrap_or(Tz::UTC);
+    Ok(Json(NamePage {
+        order: sort.order,
+        direction: sort.direction,
+        names: records
+            .into_iter()
+            .map(|record| NameEntry::new(record, tz))
+            .collect(),
+        next_cursor,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct NameQuery {
+    tz: Option<Tz>,
+}
+
+/// A single name, looked up through its aliases.
+pub async fn get_handler(
+    Path(name): Path<String>,
+    Query(query): Query<NameQuery>,
+    State(store): State<Store>,
+    State(normalizer): State<Normalizer>,
+) -> Result<Json<NameEntry>, AppError> {
+    let name = store.resolve(&normalizer.normalize(&name)).await?;
+    let record = store
+        .get(&name)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No visits recorded for '{name}'")))?;
+
+    Ok(Json(NameEntry::new(record, query.tz.unwrap_or(Tz::UTC))))
+}
+
+/// Rows removed by `DELETE /names/{name}`.
+#[derive(Serialize)]
+pub struct DeletedName {
+    name: String,
+    count: i32,
+    deleted_visits: u64,
+}
+
+/// Delete a name, looked up through its aliases, and its visit history.
+pub async fn delete_handler(
+    Path(name): Path<String>,
+    State(store): State<Store>,
+    State(normalizer): State<Normalizer>,
+) -> Result<Json<DeletedName>, AppError> {
+    let name = store.resolve(&normalizer.normalize(&name)).await?;
+    let not_found = || AppError::NotFound(format!("No visits recorded for '{name}'"));
+    let record = store.get(&name).await?.ok_or_else(not_found)?;
+    let filter = ClearFilter {
+        name: Some(name.clone()),
+        seen_before: None,
+        max_count: None,
+    };
+    let cleared = store.clear(&filter, None).await?;
+    if cleared.names == 0 {
+        return Err(not_found());
+    }
+
+    tracing::info!(name = %name, "Name deleted over HTTP");
+    Ok(Json(DeletedName {
+        name,
+        count: record.count,
+        deleted_visits: cleared.visits,
+    }))
+}
+
+/// How `blort show` prints names.
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Output {
+    /// Aligned columns for reading in a terminal
+    Table,
+    /// A single JSON array
+    Json,
+    /// Comma separated values with a header line
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+}
+
+/// Left-align `value` in a column `width` terminal cells wide, counting wide
+/// characters such as CJK or emoji as two cells.
+fn pad(value: &str, width: usize) -> String {
+    let padding = width.saturating_sub(value.width());
+    format!("{value}{}", " ".repeat(padding))
+}
+
+fn print_table(entries: &[NameEntry], title: &str, tz: Tz) {
+    let zone = if tz == Tz::UTC {
+        String::new()
+    } else {
+        format!(" ({tz})")
+    };
+    let first_seen_header = format!("First Seen{zone}");
+    let last_seen_header = format!("Last Seen{zone}");
+    let rows: Vec<[String; 4]> = entries
+        .iter()
+        .map(|entry| {
+            [
+                entry.name.clone(),
+                entry.count.to_string(),
+                entry.first_seen.format("%Y-%m-%d %H:%M:%S").to_string(),
+                entry.last_seen.format("%Y-%m-%d %H:%M:%S").to_string(),
+            ]
+        })
+        .collect();
+
+    let headers = [
+        "Name",
+        "Visits",
+        first_seen_header.as_str(),
+        last_seen_header.as_str(),
+    ];
+    let widths: Vec<usize> = (0..headers.len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].width())
+                .chain([headers[column].width()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    println!("{title}");
+    println!(
+        "{}  {}  {}  {}",
+        pad(headers[0], widths[0]),
+        pad(headers[1], widths[1]),
+        pad(headers[2], widths[2]),
+        headers[3]
+    );
+    println!("{}", "-".repeat(widths.iter().sum::<usize>() + 6));
+    for row in &rows {
+        println!(
+            "{}  {}  {}  {}",
+            pad(&row[0], widths[0]),
+            pad(&row[1], widths[1]),
+            pad(&row[2], widths[2]),
+            row[3]
+        );
+    }
+}
+
+/// Print the names matching `filter` for `blort show`.
+pub async fn show_names(
+    store: &dyn NameStore,
+    sort: Sort,
+    filter: &NameFilter,
+    limit: u32,
+    output: Output,
+    tz: Tz,
+) -> Result<(), AppError> {
+    let entries: Vec<NameEntry> = store
+        .list(sort, filter, limit, 0, None)
+        .await?
+        .into_iter()
+        .map(|record| NameEntry::new(record, tz))
+        .collect();
+
+    let mut out = io::stdout().lock();
+    match output {
+        Output::Table if entries.is_empty() => println!("No names found in database"),
+        Output::Table => print_table(
+            &entries,
+            &format!(
+                "Top {limit} names (sorted by {}, {}):",
+                sort.order.label(),
+                sort.direction.label()
+            ),
+            tz,
+        ),
+        Output::Json => {
+            serde_json::to_writer_pretty(&mut out, &entries).map_err(io::Error::from)?;
+            writeln!(out)?;
+        }
+        Output::Ndjson => {
+            for entry in &entries {
+                serde_json::to_writer(&mut out, entry).map_err(io::Error::from)?;
+                writeln!(out)?;
+            }
+        }
+        Output::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            for entry in &entries {
+                writer.serialize(entry).map_err(io::Error::from)?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
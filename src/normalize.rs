@@ -0,0 +1,133 @@
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::error::AppError;
+
+/// Pipeline turning a raw `/hello/{name}` segment into the key stored in `items`.
+///
+/// Configured through `names.normalization` (or `NAME_NORMALIZATION`), a comma
+/// separated list of steps: `nfkc`, `casefold`, `trim` and `strip-diacritics`,
+/// or `none` to store names exactly as received. Steps always run in that
+/// order, whatever order they are listed in, so that e.g. trimming also
+/// catches whitespace produced by NFKC.
+///
+/// Case folding maps the Turkish dotted capital `İ` (U+0130) to a plain `i`,
+/// so that `ALİCE` folds to `alice` rather than keeping a combining dot.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Normalizer {
+    nfkc: bool,
+    case_fold: bool,
+    trim: bool,
+    strip_diacritics: bool,
+}
+
+impl Normalizer {
+    pub fn from_steps(steps: &str) -> Result<Self, AppError> {
+        let mut normalizer = Normalizer::default();
+
+        for step in steps
+            .split(',')
+            .map(str::trim)
+            .filter(|step| !step.is_empty())
+        {
+            match step {
+                "none" => {}
+                "nfkc" => normalizer.nfkc = true,
+                "casefold" => normalizer.case_fold = true,
+                "trim" => normalizer.trim = true,
+                "strip-diacritics" => normalizer.strip_diacritics = true,
+                _ => {
+                    return Err(AppError::Config(format!(
+                        "Unknown normalization step '{step}' \
+                         (expected nfkc, casefold, trim, strip-diacritics or none)"
+                    )));
+                }
+            }
+        }
+
+        Ok(normalizer)
+    }
+
+    pub fn normalize(&self, name: &str) -> String {
+        let mut name = name.to_string();
+
+        if self.nfkc {
+            name = name.nfkc().collect();
+        }
+        if self.case_fold {
+            // The default folding of U+0130 is `i` followed by U+0307, which
+            // would keep `ALİCE` apart from `alice`. NFKC has already composed
+            // any `I` + U+0307 into U+0130.
+            name = caseless::default_case_fold_str(&name.replace('\u{130}', "i"));
+            // Folding can produce sequences that are no longer in NFKC form.
+            if self.nfkc {
+                name = name.nfkc().collect();
+            }
+        }
+        if self.strip_diacritics {
+            name = name
+                .nfd()
+                .filter(|c| !is_combining_mark(*c))
+                .nfc()
+                .collect();
+        }
+        if self.trim {
+            name = name.trim().to_string();
+        }
+
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT_STEPS: &str = "nfkc,casefold,trim";
+
+    #[test]
+    fn spellings_of_a_name_fold_together() {
+        let normalizer = Normalizer::from_steps(DEFAULT_STEPS).unwrap();
+        for spelling in [
+            "Alice",
+            "alice",
+            " alice",
+            "AL\u{130}CE",
+            "ALI\u{307}CE",
+            "\u{ff21}lice",
+        ] {
+            assert_eq!(normalizer.normalize(spelling), "alice", "{spelling:?}");
+        }
+    }
+
+    #[test]
+    fn diacritics_are_only_stripped_on_request() {
+        let default = Normalizer::from_steps(DEFAULT_STEPS).unwrap();
+        assert_eq!(default.normalize("Zo\u{eb}"), "zo\u{eb}");
+
+        let stripping = Normalizer::from_steps("nfkc,casefold,trim,strip-diacritics").unwrap();
+        assert_eq!(stripping.normalize("Zo\u{eb}"), "zoe");
+        assert_eq!(stripping.normalize("Zoe\u{308}"), "zoe");
+    }
+
+    #[test]
+    fn steps_run_in_a_fixed_order() {
+        let listed = Normalizer::from_steps("nfkc,casefold,trim,strip-diacritics").unwrap();
+        let reversed = Normalizer::from_steps("strip-diacritics,trim,casefold,nfkc").unwrap();
+        // Full-width letters and an ideographic space, only plain once NFKC ran.
+        for name in [
+            "\u{3000}\u{ff25}cole\u{301} ",
+            "  AL\u{130}CE",
+            "\u{212b}ngstr\u{f6}m",
+        ] {
+            assert_eq!(listed.normalize(name), reversed.normalize(name), "{name:?}");
+        }
+        assert_eq!(listed.normalize("\u{3000}\u{ff25}cole\u{301} "), "ecole");
+    }
+
+    #[test]
+    fn none_keeps_names_as_received() {
+        let normalizer = Normalizer::from_steps("none").unwrap();
+        assert_eq!(normalizer.normalize(" AL\u{130}CE"), " AL\u{130}CE");
+        assert!(Normalizer::from_steps("nfkc,lowercase").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::AppError;
+
+/// A range of addresses, such as `10.0.0.0/8`.
+#[derive(Clone, Copy)]
+struct Network {
+    address: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    fn parse(network: &str) -> Option<Network> {
+        let (address, prefix) = match network.split_once('/') {
+            Some((address, prefix)) => (address.parse().ok()?, Some(prefix.parse().ok()?)),
+            None => (network.parse().ok()?, None),
+        };
+        let address = IpAddr::to_canonical(&address);
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(bits);
+        (prefix <= bits).then_some(Network { address, prefix })
+    }
+
+    fn contains(self, ip: IpAddr) -> bool {
+        match (self.address, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The reverse proxies whose `X-Forwarded-For` headers are believed.
+#[derive(Clone, Default)]
+pub struct TrustedProxies(Arc<[Network]>);
+
+impl TrustedProxies {
+    /// Parse a comma separated list of addresses and CIDR ranges.
+    pub fn parse(proxies: &str) -> Result<Self, AppError> {
+        proxies
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+            .map(|proxy| {
+                Network::parse(proxy).ok_or_else(|| {
+                    AppError::Config(format!(
+                        "Invalid trusted proxy '{proxy}' (expected an address or a CIDR range)"
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|networks| TrustedProxies(networks.into()))
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|network| network.contains(ip))
+    }
+
+    /// The client behind a connection from `peer`, given the entries of its
+    /// `X-Forwarded-For` headers in order.
+    ///
+    /// Each trusted proxy appends the address it received the request from,
+    /// so the right-most entry that is not a trusted proxy is the client:
+    /// anything left of it was sent by the client and could be made up.
+    ///
+    /// The peer is only known when the router is served with
+    /// `into_make_service_with_connect_info`. Without it there is no client,
+    /// which is logged once, as is the first `X-Forwarded-For` ignored for
+    /// coming from an untrusted peer.
+    pub fn client_ip(&self, peer: Option<IpAddr>, forwarded_for: &[&str]) -> Option<IpAddr> {
+        static WARNED_WITHOUT_PEER: AtomicBool = AtomicBool::new(false);
+        static WARNED_UNTRUSTED: AtomicBool = AtomicBool::new(false);
+
+        let Some(peer) = peer else {
+            if !WARNED_WITHOUT_PEER.swap(true, Ordering::Relaxed) {
+                tracing::warn!(
+                    "Peer addresses are unknown, visits are logged without a client address \
+                     and not limited per client \
+                     (serve the router with into_make_service_with_connect_info)"
+                );
+            }
+            return None;
+        };
+        if !forwarded_for.is_empty()
+            && !self.contains(peer)
+            && !WARNED_UNTRUSTED.swap(true, Ordering::Relaxed)
+        {
+            tracing::warn!(
+                %peer,
+                "Ignoring X-Forwarded-For from a peer that is not a trusted proxy; \
+                 if it is the reverse proxy in front of the server, add it to \
+                 trusted_proxies, or every visit is logged and limited under its address"
+            );
+        }
+
+        let mut client = peer;
+        for entry in forwarded_for.iter().rev() {
+            if !self.contains(client) {
+                break;
+            }
+            match entry.parse() {
+                Ok(ip) => client = ip,
+                Err(_) => break,
+            }
+        }
+        Some(client.to_canonical())
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use sqlx::PgPool;
+
+use crate::config::{RateLimitBackend, RateLimitConfig};
+use crate::error::AppError;
+use crate::monitoring;
+use crate::store::{require_postgres, NameStore};
+
+/// How often [`RateLimiter::prune`] should run.
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A token bucket holding up to `capacity` visits, refilled continuously.
+#[derive(Clone, Copy)]
+struct Limit {
+    capacity: f64,
+    per_second: f64,
+}
+
+impl Limit {
+    fn new(per_minute: u32, burst: u32) -> Option<Limit> {
+        (per_minute > 0).then(|| Limit {
+            capacity: f64::from(burst),
+            per_second: f64::from(per_minute) / 60.0,
+        })
+    }
+
+    /// Tokens left in a bucket after `elapsed` since it held `tokens`.
+    fn refill(self, tokens: f64, elapsed: Duration) -> f64 {
+        (tokens + elapsed.as_secs_f64() * self.per_second).min(self.capacity)
+    }
+
+    /// Time until a bucket holding `tokens` has one to spare.
+    fn retry_after(self, tokens: f64) -> Duration {
+        Duration::from_secs_f64(((1.0 - tokens) / self.per_second).max(0.0))
+    }
+
+    /// Time for an empty bucket to fill up, after which it can be forgotten.
+    fn refill_time(self) -> Duration {
+        Duration::from_secs_f64(self.capacity / self.per_second)
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Where buckets and the last counted visits are kept.
+enum Buckets {
+    Memory {
+        buckets: Mutex<HashMap<String, Bucket>>,
+        counted: Mutex<HashMap<(String, String), Instant>>,
+    },
+    Postgres(PgPool),
+}
+
+struct Limiter {
+    per_client: Option<Limit>,
+    per_name: Option<Limit>,
+    duplicate_window: Duration,
+    buckets: Buckets,
+}
+
+/// Rate limits on visits, per client IP and per name, and the window within
+/// which repeat visits from a client are not counted.
+///
+/// Clients are told apart by [`VisitContext::client_ip`], the address found
+/// by [`TrustedProxies::client_ip`]. Names are the canonical ones aliases
+/// resolve to, so that visits through an alias share the limits of its name.
+///
+/// [`VisitContext::client_ip`]: crate::store::VisitContext::client_ip
+/// [`TrustedProxies::client_ip`]: crate::proxies::TrustedProxies::client_ip
+#[derive(Clone)]
+pub struct RateLimiter(Arc<Limiter>);
+
+impl RateLimiter {
+    /// A limiter keeping its state in memory, or in the database behind
+    /// `store` with the `postgres` backend.
+    pub fn new(config: &RateLimitConfig, store: &dyn NameStore) -> Result<Self, AppError> {
+        let buckets = match config.backend {
+            RateLimitBackend::Memory => Buckets::Memory {
+                buckets: Mutex::default(),
+                counted: Mutex::default(),
+            },
+            RateLimitBackend::Postgres => {
+                Buckets::Postgres(require_postgres(store, "Shared rate limits")?.clone())
+            }
+        };
+
+        Ok(RateLimiter(Arc::new(Limiter {
+            per_client: Limit::new(config.per_client_per_minute, config.burst),
+            per_name: Limit::new(config.per_name_per_minute, config.burst),
+            duplicate_window: Duration::from_secs(config.duplicate_window_secs),
+            buckets,
+        })))
+    }
+
+    /// Take one visit from the buckets of `client_ip` and `name`, or fail
+    /// with [`AppError::RateLimited`] if either is empty.
+    pub async fn check(&self, name: &str, client_ip: Option<&str>) -> Result<(), AppError> {
+        // The client goes first, so that a client over its limit does not
+        // use up the visits of the names it targets.
+        if let (Some(limit), Some(client_ip)) = (self.0.per_client, client_ip) {
+            self.take(&format!("client:{client_ip}"), limit, "client")
+                .await?;
+        }
+        if let Some(limit) = self.0.per_name {
+            self.take(&format!("name:{name}"), limit, "name").await?;
+        }
+        Ok(())
+    }
+
+    async fn take(&self, key: &str, limit: Limit, kind: &'static str) -> Result<(), AppError> {
+        let denied = match &self.0.buckets {
+            Buckets::Memory { buckets, .. } => {
+                let now = Instant::now();
+                let mut buckets = buckets.lock().unwrap();
+                let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+                    tokens: limit.capacity,
+                    updated_at: now,
+                });
+                bucket.tokens = limit.refill(bucket.tokens, now - bucket.updated_at);
+                bucket.updated_at = now;
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    None
+                } else {
+                    Some(limit.retry_after(bucket.tokens))
+                }
+            }
+            Buckets::Postgres(db) => take_shared(db, key, limit).await?,
+        };
+
+        match denied {
+            None => Ok(()),
+            Some(retry_after) => {
+                metrics::counter!("blort_rate_limited_total", "limit" => kind).increment(1);
+                Err(AppError::RateLimited(retry_after))
+            }
+        }
+    }
+
+    /// Whether a visit of `client_ip` to `name` repeats one counted within
+    /// the duplicate window.
+    pub async fn is_duplicate(
+        &self,
+        name: &str,
+        client_ip: Option<&str>,
+    ) -> Result<bool, AppError> {
+        let window = self.0.duplicate_window;
+        let Some(client_ip) = client_ip.filter(|_| !window.is_zero()) else {
+            return Ok(false);
+        };
+
+        match &self.0.buckets {
+            Buckets::Memory { counted, .. } => {
+                let key = (client_ip.to_string(), name.to_string());
+                let counted_at = counted.lock().unwrap().get(&key).copied();
+                Ok(counted_at.is_some_and(|counted_at| counted_at.elapsed() < window))
+            }
+            Buckets::Postgres(db) => {
+                let mut conn = monitoring::acquire(db).await?;
+                let duplicate = sqlx::query_scalar!(
+                    r#"SELECT EXISTS (
+                           SELECT 1 FROM counted_visits
+                           WHERE client_ip = $1 AND name = $2
+                             AND counted_at > NOW() - $3 * INTERVAL '1 second'
+                       ) AS "duplicate!""#,
+                    client_ip,
+                    name,
+                    window.as_secs_f64()
+                )
+                .fetch_one(&mut *conn)
+                .await?;
+                Ok(duplicate)
+            }
+        }
+    }
+
+    /// Start a new duplicate window for `client_ip` on `name`, once a visit
+    /// was counted.
+    pub async fn counted(&self, name: &str, client_ip: Option<&str>) -> Result<(), AppError> {
+        let Some(client_ip) = client_ip.filter(|_| !self.0.duplicate_window.is_zero()) else {
+            return Ok(());
+        };
+
+        match &self.0.buckets {
+            Buckets::Memory { counted, .. } => {
+                counted
+                    .lock()
+                    .unwrap()
+                    .insert((client_ip.to_string(), name.to_string()), Instant::now());
+            }
+            Buckets::Postgres(db) => {
+                let mut conn = monitoring::acquire(db).await?;
+                sqlx::query!(
+                    "INSERT INTO counted_visits (client_ip, name, counted_at)
+                     VALUES ($1, $2, NOW())
+                     ON CONFLICT (client_ip, name) DO UPDATE SET counted_at = NOW()",
+                    client_ip,
+                    name
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget the buckets that have filled up again and the visits older
+    /// than the duplicate window, which behave as if they never existed.
+    pub async fn prune(&self) -> Result<(), AppError> {
+        let limiter = &self.0;
+        let refill_time = [limiter.per_client, limiter.per_name]
+            .into_iter()
+            .flatten()
+            .map(Limit::refill_time)
+            .max()
+            .unwrap_or_default();
+        let window = limiter.duplicate_window;
+
+        match &limiter.buckets {
+            Buckets::Memory { buckets, counted } => {
+                let now = Instant::now();
+                buckets
+                    .lock()
+                    .unwrap()
+                    .retain(|_, bucket| now - bucket.updated_at < refill_time);
+                counted
+                    .lock()
+                    .unwrap()
+                    .retain(|_, &mut counted_at| now - counted_at < window);
+            }
+            Buckets::Postgres(db) => {
+                let mut conn = monitoring::acquire(db).await?;
+                sqlx::query!(
+                    "DELETE FROM rate_limits WHERE updated_at < NOW() - $1 * INTERVAL '1 second'",
+                    refill_time.as_secs_f64()
+                )
+                .execute(&mut *conn)
+                .await?;
+                sqlx::query!(
+                    "DELETE FROM counted_visits
+                     WHERE counted_at < NOW() - $1 * INTERVAL '1 second'",
+                    window.as_secs_f64()
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// [`RateLimiter::take`] on a bucket in the `rate_limits` table. Returns how
+/// long to wait if the bucket is empty.
+async fn take_shared(db: &PgPool, key: &str, limit: Limit) -> Result<Option<Duration>, AppError> {
+    let mut conn = monitoring::acquire(db).await?;
+    // The bucket is refilled and a token taken in a single statement, which
+    // only updates the row when a token is available.
+    let taken = sqlx::query_scalar!(
+        "INSERT INTO rate_limits (key, tokens, updated_at) VALUES ($1, $2::float8 - 1, NOW())
+         ON CONFLICT (key) DO UPDATE SET
+         tokens = LEAST(
+             $2,
+             rate_limits.tokens
+                 + EXTRACT(EPOCH FROM NOW() - rate_limits.updated_at)::float8 * $3
+         ) - 1,
+         updated_at = NOW()
+         WHERE LEAST(
+             $2,
+             rate_limits.tokens
+                 + EXTRACT(EPOCH FROM NOW() - rate_limits.updated_at)::float8 * $3
+         ) >= 1
+         RETURNING tokens",
+        key,
+        limit.capacity,
+        limit.per_second
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    if taken.is_some() {
+        return Ok(None);
+    }
+
+    let tokens = sqlx::query_scalar!(
+        r#"SELECT LEAST(
+               $2,
+               tokens + EXTRACT(EPOCH FROM NOW() - updated_at)::float8 * $3
+           ) AS "tokens!"
+           FROM rate_limits
+           WHERE key = $1"#,
+        key,
+        limit.capacity,
+        limit.per_second
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(Some(limit.retry_after(tokens.unwrap_or_default())))
+}
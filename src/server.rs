@@ -0,0 +1,397 @@
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::pin::pin;
+
+use axum::{
+    extract::{FromRef, State},
+    http::{header, HeaderMap},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+    Json, Router,
+};
+use chrono::DateTime;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+
+use crate::auth::{self, AuthPolicy, Guard, Scope};
+use crate::config::Config;
+use crate::error::AppError;
+use crate::monitoring::{self, Metrics};
+use crate::normalize::Normalizer;
+use crate::proxies::TrustedProxies;
+use crate::ratelimit::{self, RateLimiter};
+use crate::shutdown::{self, Shutdown, ShutdownTimings};
+use crate::store::{NameStore, Store, VisitContext};
+use crate::validate::{NamePolicy, Query, ValidName};
+use crate::{health, names, stats, telemetry, transfer};
+
+/// Shared state handed to every route.
+#[derive(Clone)]
+pub struct AppState {
+    pub store: Store,
+    pub normalizer: Normalizer,
+    pub policy: NamePolicy,
+    pub metrics: Metrics,
+    /// Which routes need an API key.
+    pub auth: AuthPolicy,
+    pub limiter: RateLimiter,
+    /// Proxies whose `X-Forwarded-For` headers name the client of a visit.
+    pub proxies: TrustedProxies,
+    /// Flipped by [`run_server`] on shutdown; when mounting the routes
+    /// elsewhere, call [`Shutdown::begin`] to fail readiness.
+    pub shutdown: Shutdown,
+}
+
+impl FromRef<AppState> for Store {
+    fn from_ref(state: &AppState) -> Self {
+        state.store.clone()
+    }
+}
+
+impl FromRef<AppState> for Normalizer {
+    fn from_ref(state: &AppState) -> Self {
+        state.normalizer
+    }
+}
+
+impl FromRef<AppState> for NamePolicy {
+    fn from_ref(state: &AppState) -> Self {
+        state.policy.clone()
+    }
+}
+
+impl FromRef<AppState> for Metrics {
+    fn from_ref(state: &AppState) -> Self {
+        state.metrics.clone()
+    }
+}
+
+impl FromRef<AppState> for AuthPolicy {
+    fn from_ref(state: &AppState) -> Self {
+        state.auth.clone()
+    }
+}
+
+impl FromRef<AppState> for RateLimiter {
+    fn from_ref(state: &AppState) -> Self {
+        state.limiter.clone()
+    }
+}
+
+impl FromRef<AppState> for TrustedProxies {
+    fn from_ref(state: &AppState) -> Self {
+        state.proxies.clone()
+    }
+}
+
+impl FromRef<AppState> for Shutdown {
+    fn from_ref(state: &AppState) -> Self {
+        state.shutdown.clone()
+    }
+}
+
+async fn hello_world() -> &'static str {
+    "Hello, world!"
+}
+
+/// Outcome of a single visit to `/hello/{name}`.
+#[derive(Serialize)]
+pub struct HelloResponse {
+    pub name: String,
+    pub previous_count: i32,
+    pub new_count: i32,
+    pub first_visit: bool,
+    /// The visit repeats one from the same client within the duplicate
+    /// window, and was logged without being counted.
+    pub duplicate: bool,
+    pub last_seen: Option<DateTime<Tz>>,
+}
+
+impl HelloResponse {
+    pub fn in_timezone(self, tz: Tz) -> Self {
+        HelloResponse {
+            last_seen: self.last_seen.map(|last_seen| last_seen.with_timezone(&tz)),
+            ..self
+        }
+    }
+
+    /// Plain text greeting returned by `/hello/{name}`.
+    pub fn message(&self) -> String {
+        let name = &self.name;
+        let previous_count = self.previous_count;
+        match self.last_seen {
+            Some(last_seen) if last_seen.timezone() == Tz::UTC => format!(
+                "Hello {name}! You've been called {previous_count} times previously. Last seen: {}",
+                last_seen.naive_local()
+            ),
+            Some(last_seen) => format!(
+                "Hello {name}! You've been called {previous_count} times previously. Last seen: {} {}",
+                last_seen.naive_local(),
+                last_seen.format("%Z")
+            ),
+            None => format!("Hello {name}! This is your first visit!"),
+        }
+    }
+}
+
+/// Optional `tz` query parameter selecting the zone timestamps are rendered in.
+#[derive(Deserialize)]
+struct TimeZoneQuery {
+    tz: Option<Tz>,
+}
+
+/// Whether the client asked for a JSON body through its `Accept` header.
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|range| range.split(';').next())
+        .any(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Count a visit through `store` and describe it for the response.
+pub async fn record_visit(
+    store: &dyn NameStore,
+    name: String,
+    context: VisitContext,
+) -> Result<HelloResponse, AppError> {
+    let record = store
+        .record_visit(&name, &context)
+        .await
+        .inspect_err(monitoring::record_upsert_error)?;
+
+    let previous_count = record.count - 1;
+    let last_seen = record
+        .previous_seen
+        .map(|previous_seen| previous_seen.with_timezone(&Tz::UTC));
+
+    Ok(HelloResponse {
+        name: record.name,
+        previous_count,
+        new_count: previous_count + 1,
+        first_visit: previous_count == 0,
+        duplicate: false,
+        last_seen,
+    })
+}
+
+/// Count a visit unless the client is over its rate limit or repeats a
+/// recent visit, which is only logged and answered with the current count.
+async fn visit(
+    limiter: &RateLimiter,
+    store: &dyn NameStore,
+    name: String,
+    context: VisitContext,
+) -> Result<HelloResponse, AppError> {
+    let name = store.resolve(&name).await?;
+    let client_ip = context.client_ip.clone();
+    limiter.check(&name, client_ip.as_deref()).await?;
+
+    if limiter.is_duplicate(&name, client_ip.as_deref()).await?
+        && let Some(record) = store.record_duplicate(&name, &context).await?
+    {
+        metrics::counter!("blort_duplicate_visits_total").increment(1);
+        return Ok(HelloResponse {
+            name: record.name,
+            previous_count: record.count,
+            new_count: record.count,
+            first_visit: false,
+            duplicate: true,
+            last_seen: Some(record.last_seen.with_timezone(&Tz::UTC)),
+        });
+    }
+
+    let visit = record_visit(store, name, context).await?;
+    // Only a visit that was counted starts a window, so that a failed one
+    // is counted when retried.
+    if let Err(e) = limiter.counted(&visit.name, client_ip.as_deref()).await {
+        tracing::warn!(error = %e, "Cannot start the duplicate window of a visit");
+    }
+    Ok(visit)
+}
+
+async fn hello_name(
+    ValidName(name): ValidName,
+    Query(query): Query<TimeZoneQuery>,
+    State(store): State<Store>,
+    State(limiter): State<RateLimiter>,
+    headers: HeaderMap,
+    context: VisitContext,
+) -> Result<Response, AppError> {
+    let visit = visit(&limiter, &*store, name, context)
+        .await?
+        .in_timezone(query.tz.unwrap_or(Tz::UTC));
+
+    // The body depends on Accept, which shared caches have to know.
+    let vary = [(header::VARY, "Accept")];
+    if wants_json(&headers) {
+        Ok((vary, Json(visit)).into_response())
+    } else {
+        Ok((vary, visit.message()).into_response())
+    }
+}
+
+async fn api_hello_name(
+    ValidName(name): ValidName,
+    Query(query): Query<TimeZoneQuery>,
+    State(store): State<Store>,
+    State(limiter): State<RateLimiter>,
+    context: VisitContext,
+) -> Result<Json<HelloResponse>, AppError> {
+    let visit = visit(&limiter, &*store, name, context).await?;
+    Ok(Json(visit.in_timezone(query.tz.unwrap_or(Tz::UTC))))
+}
+
+/// Every route of the application, with its middleware.
+///
+/// Routes are grouped by the [`Scope`] they require; health checks are
+/// always public.
+pub fn router(state: AppState) -> Router {
+    let require = |scope| {
+        let guard = Guard::new(state.store.clone(), state.auth.clone(), scope);
+        middleware::from_fn_with_state(guard, auth::authorize)
+    };
+
+    let visit = Router::new()
+        .route("/hello/{name}", get(hello_name))
+        .route("/api/v1/hello/{name}", get(api_hello_name))
+        .route_layer(require(Scope::Visit));
+    let read = Router::new()
+        .route("/stats/{name}", get(stats::stats_handler))
+        .route("/names", get(names::list_handler))
+        .route("/names/{name}", get(names::get_handler))
+        .route_layer(require(Scope::Read));
+    let metrics = Router::new()
+        .route("/metrics", get(monitoring::metrics_handler))
+        .route_layer(require(Scope::Metrics));
+    let export = Router::new()
+        .route("/export", get(transfer::export_handler))
+        .route_layer(require(Scope::Export));
+    let admin = Router::new()
+        .route("/names/{name}", delete(names::delete_handler))
+        .route_layer(require(Scope::Admin));
+
+    Router::new()
+        .route("/", get(hello_world))
+        .route("/ok", get(health::livez))
+        .route("/livez", get(health::livez))
+        .route("/readyz", get(health::readyz))
+        .merge(visit)
+        .merge(read)
+        .merge(metrics)
+        .merge(export)
+        .merge(admin)
+        .layer(middleware::from_fn(monitoring::track_requests))
+        .with_state(state)
+        .layer(telemetry::trace_layer())
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
+
+/// Serve the application on `config.bind` until SIGINT or SIGTERM, then
+/// drain in-flight requests and close the store, giving up on both once the
+/// drain timeout has passed.
+pub async fn run_server(
+    store: Store,
+    config: &Config,
+    normalizer: Normalizer,
+    policy: NamePolicy,
+) -> Result<(), AppError> {
+    let metrics = Metrics::install()?;
+    let auth = AuthPolicy::new(&config.auth)?;
+    let limiter = RateLimiter::new(&config.rate_limit, &*store)?;
+    tokio::spawn({
+        let limiter = limiter.clone();
+        async move {
+            let mut interval = tokio::time::interval(ratelimit::PRUNE_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = limiter.prune().await {
+                    tracing::warn!(error = %e, "Cannot prune rate limits");
+                }
+            }
+        }
+    });
+    let timings = ShutdownTimings::from(&config.shutdown);
+    let shutdown = Shutdown::default();
+
+    let router = router(AppState {
+        store: store.clone(),
+        normalizer,
+        policy,
+        metrics,
+        auth,
+        limiter,
+        proxies: TrustedProxies::parse(&config.trusted_proxies)?,
+        shutdown: shutdown.clone(),
+    });
+
+    let addr = config.bind;
+    tracing::info!("Server running on http://{addr}");
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown::signal().await;
+            tracing::info!("Shutting down, readiness now failing");
+            shutdown.begin();
+        }
+    });
+
+    // Once draining starts, keep accepting connections for the configured
+    // delay, then stop listening and let in-flight requests complete.
+    let server = axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown.draining().await;
+            tokio::time::sleep(timings.delay).await;
+            tracing::info!("Stopped accepting connections, draining in-flight requests");
+        }
+    });
+
+    let mut deadline = pin!(async {
+        shutdown.draining().await;
+        tokio::time::sleep(timings.delay + timings.drain_timeout).await;
+    });
+    let drained = tokio::select! {
+        result = server.into_future() => {
+            result?;
+            true
+        }
+        () = &mut deadline => false,
+    };
+
+    // Closing the pool waits for every connection to come back, including
+    // those held by abandoned requests or a running export, so it is given
+    // whatever is left of the deadline.
+    if !drained {
+        tracing::warn!(
+            "Requests still in flight after {:?}, exiting without waiting for them",
+            timings.drain_timeout
+        );
+        return Ok(());
+    }
+    tokio::select! {
+        () = store.close() => {
+            tracing::info!("Database connections closed, shutdown complete");
+        }
+        () = &mut deadline => {
+            tracing::warn!(
+                "Database connections still in use after {:?}, exiting without closing them",
+                timings.drain_timeout
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::config::ShutdownConfig;
+
+/// Shared flag flipped once the server starts shutting down.
+///
+/// While draining, `/readyz` reports the instance as not ready so that load
+/// balancers stop routing new traffic to it.
+#[derive(Clone)]
+pub struct Shutdown {
+    draining: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown {
+            draining: Arc::new(watch::Sender::new(false)),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn begin(&self) {
+        self.draining.send_replace(true);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.draining.borrow()
+    }
+
+    /// Resolves once [`Shutdown::begin`] has been called.
+    pub async fn draining(&self) {
+        let mut receiver = self.draining.subscribe();
+        // The sender lives in `self`, so the channel cannot close while we wait.
+        let _ = receiver.wait_for(|draining| *draining).await;
+    }
+}
+
+/// Timings of the shutdown sequence:
+/// - `delay`: how long to keep accepting connections with readiness failing,
+///   giving load balancers time to notice
+/// - `drain_timeout`: how long in-flight requests get to complete once the
+///   listener is closed, before they are dropped
+pub struct ShutdownTimings {
+    pub delay: Duration,
+    pub drain_timeout: Duration,
+}
+
+impl From<&ShutdownConfig> for ShutdownTimings {
+    fn from(config: &ShutdownConfig) -> Self {
+        ShutdownTimings {
+            delay: Duration::from_secs(config.delay_secs),
+            drain_timeout: Duration::from_secs(config.drain_timeout_secs),
+        }
+    }
+}
+
+/// Wait for SIGINT (Ctrl-C) or, on Unix, SIGTERM.
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!(error = %e, "cannot listen for Ctrl-C");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "cannot listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => tracing::info!("Received SIGINT"),
+        () = terminate => tracing::info!("Received SIGTERM"),
+    }
+}
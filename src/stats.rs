@@ -0,0 +1,397 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::{
+    DateTime, Datelike, Duration, NaiveDateTime, NaiveTime, Offset, TimeZone, Timelike, Utc,
+};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+use crate::normalize::Normalizer;
+use crate::store::{NameStore, Store};
+use crate::validate::Query;
+
+/// Refuse ranges that would produce more buckets than this, so a careless
+/// `granularity=hour&since=10y` cannot make the store generate millions of
+/// rows.
+const MAX_BUCKETS: i64 = 10_000;
+
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Hour,
+    #[default]
+    Day,
+    Week,
+}
+
+impl Granularity {
+    /// Field name understood by Postgres' `date_trunc`.
+    pub fn as_sql(self) -> &'static str {
+        match self {
+            Granularity::Hour => "hour",
+            Granularity::Day => "day",
+            Granularity::Week => "week",
+        }
+    }
+
+    fn duration(self) -> Duration {
+        match self {
+            Granularity::Hour => Duration::hours(1),
+            Granularity::Day => Duration::days(1),
+            Granularity::Week => Duration::weeks(1),
+        }
+    }
+
+    /// Start of the bucket holding local wall clock time `time`, as given by
+    /// `date_trunc`: weeks start on Mondays.
+    fn truncate(self, time: NaiveDateTime) -> NaiveDateTime {
+        let date = time.date();
+        match self {
+            Granularity::Hour => {
+                date.and_time(NaiveTime::MIN) + Duration::hours(time.hour().into())
+            }
+            Granularity::Day => date.and_time(NaiveTime::MIN),
+            Granularity::Week => {
+                let monday = date - Duration::days(date.weekday().num_days_from_monday().into());
+                monday.and_time(NaiveTime::MIN)
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Bucket {
+    pub start: DateTime<Tz>,
+    pub visits: i64,
+}
+
+#[derive(Serialize)]
+pub struct VisitStats {
+    pub name: String,
+    pub granularity: Granularity,
+    pub since: DateTime<Tz>,
+    pub until: DateTime<Tz>,
+    pub total: i64,
+    pub buckets: Vec<Bucket>,
+}
+
+/// Counts visits into the buckets of a range, for the stores that cannot
+/// generate them in SQL. Buckets follow local wall clock time in `tz`, like
+/// those of the Postgres store.
+pub struct BucketCounter {
+    granularity: Granularity,
+    tz: Tz,
+    first: NaiveDateTime,
+    buckets: Vec<Bucket>,
+}
+
+impl BucketCounter {
+    /// Every bucket between `since` and `until`, without visits yet.
+    pub fn new(
+        granularity: Granularity,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        tz: Tz,
+    ) -> Self {
+        let first = granularity.truncate(since.with_timezone(&tz).naive_local());
+        let last = (until - Duration::microseconds(1))
+            .with_timezone(&tz)
+            .naive_local();
+
+        let mut buckets = Vec::new();
+        let mut start = first;
+        while start <= last {
+            buckets.push(Bucket {
+                start: local_instant(tz, start),
+                visits: 0,
+            });
+            start += granularity.duration();
+        }
+
+        BucketCounter {
+            granularity,
+            tz,
+            first,
+            buckets,
+        }
+    }
+
+    /// Count a visit, which has to fall within the range.
+    pub fn add(&mut self, visited_at: DateTime<Utc>) {
+        let start = self
+            .granularity
+            .truncate(visited_at.with_timezone(&self.tz).naive_local());
+        let index = (start - self.first).num_seconds() / self.granularity.duration().num_seconds();
+        if let Some(bucket) = usize::try_from(index)
+            .ok()
+            .and_then(|index| self.buckets.get_mut(index))
+        {
+            bucket.visits += 1;
+        }
+    }
+
+    pub fn finish(self) -> Vec<Bucket> {
+        self.buckets
+    }
+}
+
+/// The instant at local wall clock time `time` in `tz`. A time skipped by a
+/// daylight saving change is read with the offset in effect before it, as
+/// Postgres does.
+fn local_instant(tz: Tz, time: NaiveDateTime) -> DateTime<Tz> {
+    tz.from_local_datetime(&time).earliest().unwrap_or_else(|| {
+        let offset = tz
+            .offset_from_utc_datetime(&(time - Duration::days(1)))
+            .fix();
+        let utc = time - Duration::seconds(offset.local_minus_utc().into());
+        utc.and_utc().with_timezone(&tz)
+    })
+}
+
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    #[serde(default)]
+    granularity: Granularity,
+    since: Option<String>,
+    until: Option<String>,
+    tz: Option<Tz>,
+}
+
+/// Parse either a relative age such as `90m`, `12h`, `30d` or `2w` (counted
+/// back from `now`), or an absolute RFC 3339 timestamp.
+pub fn parse_time(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    ago(now, parse_age(value)?)
+}
+
+/// The time `age` before `now`, or an error if it predates the calendar.
+pub fn ago(now: DateTime<Utc>, age: Duration) -> Result<DateTime<Utc>, String> {
+    now.checked_sub_signed(age)
+        .ok_or_else(|| format!("Age out of range: {} days", age.num_days()))
+}
+
+/// Parse a relative age such as `90m`, `12h`, `30d` or `2w`.
+pub fn parse_age(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("Missing unit in '{value}' (expected m, h, d or w)"))?;
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| format!("Invalid amount in '{value}'"))?;
+
+    let age = match unit {
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => {
+            return Err(format!(
+                "Unknown unit '{unit}' in '{value}' (expected m, h, d or w)"
+            ))
+        }
+    };
+    age.ok_or_else(|| format!("Age out of range: '{value}'"))
+}
+
+/// Visits to `name` per `granularity` between `since` and `until`, see
+/// [`NameStore::visit_stats`].
+pub async fn visit_stats(
+    store: &dyn NameStore,
+    name: String,
+    granularity: Granularity,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    tz: Tz,
+) -> Result<VisitStats, AppError> {
+    if since >= until {
+        return Err(AppError::Invalid(format!(
+            "Start of range ({since}) must be before its end ({until})"
+        )));
+    }
+    if (until - since).num_seconds() / granularity.duration().num_seconds() > MAX_BUCKETS {
+        return Err(AppError::Invalid(format!(
+            "Range too large: more than {MAX_BUCKETS} buckets at this granularity"
+        )));
+    }
+
+    let buckets = store
+        .visit_stats(&name, granularity, since, until, tz)
+        .await?;
+
+    Ok(VisitStats {
+        name,
+        granularity,
+        since: since.with_timezone(&tz),
+        until: until.with_timezone(&tz),
+        total: buckets.iter().map(|bucket| bucket.visits).sum(),
+        buckets,
+    })
+}
+
+pub async fn stats_handler(
+    Path(name): Path<String>,
+    Query(query): Query<StatsQuery>,
+    State(store): State<Store>,
+    State(normalizer): State<Normalizer>,
+) -> Result<Json<VisitStats>, AppError> {
+    let name = store.resolve(&normalizer.normalize(&name)).await?;
+    let now = Utc::now();
+    let since =
+        parse_time(query.since.as_deref().unwrap_or("7d"), now).map_err(AppError::Invalid)?;
+    let until = match query.until.as_deref() {
+        Some(until) => parse_time(until, now).map_err(AppError::Invalid)?,
+        None => now,
+    };
+
+    visit_stats(
+        &*store,
+        name,
+        query.granularity,
+        since,
+        until,
+        query.tz.unwrap_or(Tz::UTC),
+    )
+    .await
+    .map(Json)
+}
+
+pub async fn show_stats(
+    store: &dyn NameStore,
+    name: String,
+    granularity: Granularity,
+    since: &str,
+) -> Result<(), AppError> {
+    let now = Utc::now();
+    let since = parse_time(since, now).map_err(AppError::Invalid)?;
+    let stats = visit_stats(store, name, granularity, since, now, Tz::UTC).await?;
+
+    let format = match granularity {
+        Granularity::Hour => "%Y-%m-%d %H:00",
+        Granularity::Day | Granularity::Week => "%Y-%m-%d",
+    };
+
+    println!(
+        "Visits for {} per {} since {}:",
+        stats.name,
+        granularity.as_sql(),
+        stats.since.format("%Y-%m-%d %H:%M:%S")
+    );
+    println!("{:<20} Visits", "Period");
+    println!("{}", "-".repeat(30));
+
+    for bucket in &stats.buckets {
+        println!(
+            "{:<20} {}",
+            bucket.start.format(format).to_string(),
+            bucket.visits
+        );
+    }
+
+    println!("{}", "-".repeat(30));
+    println!("{:<20} {}", "Total", stats.total);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        "2025-03-01T12:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn ages_are_parsed_in_every_unit() {
+        assert_eq!(parse_age("90m"), Ok(Duration::minutes(90)));
+        assert_eq!(parse_age(" 12h "), Ok(Duration::hours(12)));
+        assert_eq!(parse_age("30d"), Ok(Duration::days(30)));
+        assert_eq!(parse_age("2w"), Ok(Duration::weeks(2)));
+
+        for invalid in ["", "12", "d", "12y", "-3d", "1.5h"] {
+            assert!(parse_age(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn out_of_range_ages_are_errors() {
+        assert!(parse_age("9999999999999w").is_err());
+        assert!(parse_age("99999999999999999999m").is_err());
+        assert!(parse_time("100000000d", now()).is_err());
+    }
+
+    #[test]
+    fn times_are_ages_or_timestamps() {
+        assert_eq!(
+            parse_time("36h", now()),
+            Ok("2025-02-28T00:00:00Z".parse().unwrap())
+        );
+        assert_eq!(
+            parse_time("2025-01-01T01:00:00+01:00", now()),
+            Ok("2025-01-01T00:00:00Z".parse().unwrap())
+        );
+        assert!(parse_time("yesterday", now()).is_err());
+    }
+
+    fn starts(buckets: &[Bucket]) -> Vec<String> {
+        buckets
+            .iter()
+            .map(|bucket| bucket.start.to_rfc3339())
+            .collect()
+    }
+
+    #[test]
+    fn buckets_follow_local_time_across_daylight_saving() {
+        let tz: Tz = "America/New_York".parse().unwrap();
+        let mut counter = BucketCounter::new(
+            Granularity::Day,
+            "2025-03-08T12:00:00Z".parse().unwrap(),
+            "2025-03-11T04:00:00Z".parse().unwrap(),
+            tz,
+        );
+        // Late on the 9th in New York, already the 10th in UTC.
+        counter.add("2025-03-10T03:30:00Z".parse().unwrap());
+        let buckets = counter.finish();
+
+        assert_eq!(
+            starts(&buckets),
+            [
+                "2025-03-08T00:00:00-05:00",
+                "2025-03-09T00:00:00-05:00",
+                "2025-03-10T00:00:00-04:00",
+            ]
+        );
+        let visits: Vec<i64> = buckets.iter().map(|bucket| bucket.visits).collect();
+        assert_eq!(visits, [0, 1, 0]);
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        let mut counter = BucketCounter::new(
+            Granularity::Week,
+            "2025-02-20T00:00:00Z".parse().unwrap(),
+            "2025-03-09T23:00:00Z".parse().unwrap(),
+            "Europe/Paris".parse().unwrap(),
+        );
+        counter.add("2025-03-05T10:00:00Z".parse().unwrap());
+        let buckets = counter.finish();
+
+        assert_eq!(
+            starts(&buckets),
+            [
+                "2025-02-17T00:00:00+01:00",
+                "2025-02-24T00:00:00+01:00",
+                "2025-03-03T00:00:00+01:00",
+            ]
+        );
+        assert_eq!(buckets[2].visits, 1);
+    }
+}
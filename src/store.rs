@@ -0,0 +1,457 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{header, request::Parts, HeaderName, HeaderValue},
+};
+use chrono::{DateTime, SubsecRound, Utc};
+use chrono_tz::Tz;
+use sqlx::PgPool;
+
+use crate::auth::KeyRecord;
+use crate::clear::ClearFilter;
+use crate::config::DatabaseConfig;
+use crate::error::AppError;
+use crate::names::{Cursor, NameFilter, NameRecord, Sort};
+use crate::proxies::TrustedProxies;
+use crate::stats::{Bucket, Granularity};
+use crate::transfer::Record;
+
+mod memory;
+mod postgres;
+mod sqlite;
+
+pub use memory::MemoryStore;
+pub use postgres::PgStore;
+pub use sqlite::SqliteStore;
+
+/// Request metadata stored alongside every visit.
+pub struct VisitContext {
+    /// See [`TrustedProxies::client_ip`].
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+}
+
+impl<S> FromRequestParts<S> for VisitContext
+where
+    S: Send + Sync,
+    TrustedProxies: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let header_value = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(str::to_owned)
+        };
+
+        let forwarded_for: Vec<&str> = parts
+            .headers
+            .get_all(HeaderName::from_static("x-forwarded-for"))
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let client_ip = TrustedProxies::from_ref(state).client_ip(peer, &forwarded_for);
+
+        Ok(VisitContext {
+            client_ip: client_ip.map(|ip| ip.to_string()),
+            user_agent: header_value(header::USER_AGENT),
+            referer: header_value(header::REFERER),
+        })
+    }
+}
+
+/// State of a name right after a visit was counted.
+pub struct RecordedVisit {
+    /// Canonical name the visit was counted under.
+    pub name: String,
+    pub count: i32,
+    /// When the name was last seen before this visit, `None` on a first visit.
+    pub previous_seen: Option<DateTime<Utc>>,
+}
+
+/// Numbers of rows removed by [`NameStore::clear`].
+pub struct Cleared {
+    pub names: u64,
+    pub visits: u64,
+}
+
+/// Receives the rows deleted by [`NameStore::clear`] one at a time, as they
+/// are deleted.
+pub trait Dump: Send {
+    fn write(&mut self, record: Record) -> Result<(), AppError>;
+
+    /// Called once every deleted row was written, before the deletion is
+    /// committed. An error here or from [`Dump::write`] keeps every row in
+    /// place.
+    fn finish(&mut self) -> Result<(), AppError>;
+}
+
+/// Where names and their visits are kept.
+///
+/// Postgres is the production backend. SQLite and the in-memory store let
+/// the application run without a database server, e.g. in development, CI
+/// or tests; they have no aliases, and the commands built on Postgres-only
+/// features reach the pool through [`NameStore::postgres`].
+#[async_trait]
+pub trait NameStore: Send + Sync {
+    /// Count a visit to `name`, resolving aliases, and log it with `context`.
+    async fn record_visit(
+        &self,
+        name: &str,
+        context: &VisitContext,
+    ) -> Result<RecordedVisit, AppError>;
+
+    /// Log a visit to the canonical `name` without counting it, for a repeat
+    /// within the duplicate window. Returns the current state of `name`, or
+    /// `None` without logging anything if it has never been counted.
+    async fn record_duplicate(
+        &self,
+        name: &str,
+        context: &VisitContext,
+    ) -> Result<Option<NameRecord>, AppError>;
+
+    async fn get(&self, name: &str) -> Result<Option<NameRecord>, AppError>;
+
+    /// Names matching `filter` in `sort` order, skipping `offset` rows after
+    /// `after`.
+    async fn list(
+        &self,
+        sort: Sort,
+        filter: &NameFilter,
+        limit: u32,
+        offset: u32,
+        after: Option<&Cursor>,
+    ) -> Result<Vec<NameRecord>, AppError>;
+
+    /// Number of distinct names.
+    async fn count_names(&self) -> Result<i64, AppError>;
+
+    /// Number of names and visits [`NameStore::clear`] would delete.
+    async fn count(&self, filter: &ClearFilter) -> Result<(i64, i64), AppError>;
+
+    /// Delete the names matching `filter` together with their visits, all
+    /// or nothing. Rows are only read back when a `dump` is given, which
+    /// receives the visits first, then the names.
+    async fn clear(
+        &self,
+        filter: &ClearFilter,
+        dump: Option<&mut dyn Dump>,
+    ) -> Result<Cleared, AppError>;
+
+    /// Visits to `name` from `since` up to `until`, counted per
+    /// `granularity`. Every bucket of the range is returned, including empty
+    /// ones, and days and weeks start at midnight in `tz`.
+    async fn visit_stats(
+        &self,
+        name: &str,
+        granularity: Granularity,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<Vec<Bucket>, AppError>;
+
+    /// Store an API key under `name`, returning its id. Only the hash of the
+    /// key and its first characters are kept.
+    async fn insert_key(
+        &self,
+        name: &str,
+        prefix: &str,
+        key_hash: &[u8],
+        scopes: &[&str],
+    ) -> Result<i64, AppError>;
+
+    /// Scopes of the active key with this hash, recording its use.
+    async fn use_key(&self, key_hash: &[u8]) -> Result<Option<Vec<String>>, AppError>;
+
+    /// Mark a key as revoked. Returns whether an active key had this id.
+    async fn revoke_key(&self, id: i64) -> Result<bool, AppError>;
+
+    /// Every key, revoked ones included, by id.
+    async fn list_keys(&self) -> Result<Vec<KeyRecord>, AppError>;
+
+    /// Canonical name that visits to `name` are counted under.
+    async fn resolve(&self, name: &str) -> Result<String, AppError> {
+        Ok(name.to_string())
+    }
+
+    /// Check that the backend answers.
+    async fn ping(&self) -> Result<(), AppError>;
+
+    /// Release the connections, once the server is done with the store.
+    async fn close(&self) {}
+
+    /// The connection pool, for the features only implemented on Postgres.
+    fn postgres(&self) -> Option<&PgPool> {
+        None
+    }
+}
+
+/// Shared handle to the store selected at startup.
+pub type Store = Arc<dyn NameStore>;
+
+/// Backends, told apart by the scheme of the database URL.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// `postgres://` or `postgresql://`
+    Postgres,
+    /// `sqlite:path/to/file.db` or `sqlite::memory:`
+    Sqlite,
+    /// `memory://`, nothing is persisted
+    Memory,
+}
+
+impl Backend {
+    pub fn from_url(url: &str) -> Option<Backend> {
+        match url.split_once(':')?.0 {
+            "postgres" | "postgresql" => Some(Backend::Postgres),
+            "sqlite" => Some(Backend::Sqlite),
+            "memory" => Some(Backend::Memory),
+            _ => None,
+        }
+    }
+}
+
+/// Open the store `url` points to. SQLite databases are created and
+/// migrated as needed; Postgres is migrated by the caller.
+pub async fn open(url: &str, config: &DatabaseConfig) -> Result<Store, AppError> {
+    match Backend::from_url(url) {
+        Some(Backend::Postgres) => Ok(Arc::new(PgStore::connect(url, config).await?)),
+        Some(Backend::Sqlite) => Ok(Arc::new(SqliteStore::connect(url, config).await?)),
+        Some(Backend::Memory) => Ok(Arc::new(MemoryStore::default())),
+        None => Err(AppError::Config(
+            "Unsupported database URL scheme".to_string(),
+        )),
+    }
+}
+
+/// The Postgres pool behind `store`, or an error naming the `feature` that
+/// needs it.
+pub fn require_postgres<'a>(
+    store: &'a dyn NameStore,
+    feature: &str,
+) -> Result<&'a PgPool, AppError> {
+    store
+        .postgres()
+        .ok_or_else(|| AppError::Unsupported(format!("{feature} requires a Postgres database")))
+}
+
+/// Current time at the microsecond precision every backend stores.
+fn now() -> DateTime<Utc> {
+    Utc::now().trunc_subsecs(6)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::names::{Direction, OrderBy};
+
+    fn context() -> VisitContext {
+        VisitContext {
+            client_ip: Some("127.0.0.1".to_string()),
+            user_agent: None,
+            referer: None,
+        }
+    }
+
+    async fn stores() -> Vec<Store> {
+        let sqlite = SqliteStore::connect("sqlite::memory:", &DatabaseConfig::default())
+            .await
+            .unwrap();
+        vec![Arc::new(MemoryStore::default()), Arc::new(sqlite)]
+    }
+
+    /// Keeps the records it is given, failing once finished if `fail` is set.
+    struct Collected {
+        records: Vec<Record>,
+        fail: bool,
+    }
+
+    impl Dump for Collected {
+        fn write(&mut self, record: Record) -> Result<(), AppError> {
+            self.records.push(record);
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<(), AppError> {
+            if self.fail {
+                return Err(AppError::Invalid("abort".to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn visits_are_counted() {
+        for store in stores().await {
+            let first = store.record_visit("alice", &context()).await.unwrap();
+            assert_eq!(first.count, 1);
+            assert!(first.previous_seen.is_none());
+
+            let second = store.record_visit("alice", &context()).await.unwrap();
+            assert_eq!(second.count, 2);
+            assert!(second.previous_seen.is_some());
+
+            let record = store.get("alice").await.unwrap().unwrap();
+            assert_eq!(record.count, 2);
+            assert!(record.first_seen <= record.last_seen);
+            assert!(store.get("bob").await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn listing_pages_through_every_name() {
+        for store in stores().await {
+            for (name, visits) in [("carol", 2), ("alice", 3), ("bob", 2), ("dave", 1)] {
+                for _ in 0..visits {
+                    store.record_visit(name, &context()).await.unwrap();
+                }
+            }
+
+            let sort = Sort::new(OrderBy::Visits, Some(Direction::Desc));
+            let filter = NameFilter::default();
+            let first = store.list(sort, &filter, 2, 0, None).await.unwrap();
+            let cursor = Cursor::after(sort.order, first.last().unwrap());
+            let second = store
+                .list(sort, &filter, 2, 0, Some(&cursor))
+                .await
+                .unwrap();
+
+            let names: Vec<&str> = first
+                .iter()
+                .chain(&second)
+                .map(|record| record.name.as_str())
+                .collect();
+            assert_eq!(names, ["alice", "carol", "bob", "dave"]);
+
+            let filter = NameFilter {
+                min_visits: Some(2),
+                name_prefix: Some("b".to_string()),
+                ..NameFilter::default()
+            };
+            let sort = Sort::new(OrderBy::Name, None);
+            let matching = store.list(sort, &filter, 10, 0, None).await.unwrap();
+            assert_eq!(matching.len(), 1);
+            assert_eq!(matching[0].name, "bob");
+        }
+    }
+
+    #[tokio::test]
+    async fn duplicates_are_logged_without_counting() {
+        for store in stores().await {
+            assert!(store
+                .record_duplicate("alice", &context())
+                .await
+                .unwrap()
+                .is_none());
+
+            store.record_visit("alice", &context()).await.unwrap();
+            let record = store
+                .record_duplicate("alice", &context())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(record.count, 1);
+            assert_eq!(store.get("alice").await.unwrap().unwrap().count, 1);
+
+            let everything = ClearFilter {
+                name: None,
+                seen_before: None,
+                max_count: None,
+            };
+            assert_eq!(store.count(&everything).await.unwrap(), (1, 2));
+        }
+    }
+
+    #[tokio::test]
+    async fn visit_stats_count_the_visits_of_a_name() {
+        for store in stores().await {
+            store.record_visit("alice", &context()).await.unwrap();
+            store.record_visit("alice", &context()).await.unwrap();
+            store.record_visit("bob", &context()).await.unwrap();
+
+            let until = Utc::now() + chrono::Duration::minutes(1);
+            let since = until - chrono::Duration::days(2);
+            let buckets = store
+                .visit_stats("alice", Granularity::Day, since, until, Tz::UTC)
+                .await
+                .unwrap();
+            assert!((2..=3).contains(&buckets.len()));
+            let visits: i64 = buckets.iter().map(|bucket| bucket.visits).sum();
+            assert_eq!(visits, 2);
+            assert_eq!(buckets.last().unwrap().visits, 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn keys_are_looked_up_by_hash() {
+        for store in stores().await {
+            let id = store
+                .insert_key("ci", "blort_0123ab", b"hash", &["read", "export"])
+                .await
+                .unwrap();
+            assert_eq!(
+                store.use_key(b"hash").await.unwrap(),
+                Some(vec!["read".to_string(), "export".to_string()])
+            );
+            assert_eq!(store.use_key(b"other").await.unwrap(), None);
+
+            assert!(store.revoke_key(id).await.unwrap());
+            assert!(!store.revoke_key(id).await.unwrap());
+            assert_eq!(store.use_key(b"hash").await.unwrap(), None);
+
+            let keys = store.list_keys().await.unwrap();
+            assert_eq!(keys.len(), 1);
+            assert_eq!(keys[0].name, "ci");
+            assert!(keys[0].last_used_at.is_some() && keys[0].revoked_at.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn clear_keeps_everything_when_aborted() {
+        for store in stores().await {
+            store.record_visit("alice", &context()).await.unwrap();
+            store.record_visit("bob", &context()).await.unwrap();
+            let filter = ClearFilter {
+                name: Some("alice".to_string()),
+                seen_before: None,
+                max_count: None,
+            };
+            assert_eq!(store.count(&filter).await.unwrap(), (1, 1));
+
+            let mut failing = Collected {
+                records: Vec::new(),
+                fail: true,
+            };
+            assert!(store.clear(&filter, Some(&mut failing)).await.is_err());
+            assert!(store.get("alice").await.unwrap().is_some());
+
+            let mut dump = Collected {
+                records: Vec::new(),
+                fail: false,
+            };
+            let cleared = store.clear(&filter, Some(&mut dump)).await.unwrap();
+            assert_eq!((cleared.names, cleared.visits), (1, 1));
+            assert!(matches!(
+                dump.records.as_slice(),
+                [Record::Visit(visit), Record::Item(item)]
+                    if visit.name == "alice" && item.name == "alice" && item.count == 1
+            ));
+            assert!(store.get("alice").await.unwrap().is_none());
+            assert!(store.get("bob").await.unwrap().is_some());
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+use super::{now, Cleared, Dump, NameStore, RecordedVisit, VisitContext};
+use crate::auth::KeyRecord;
+use crate::clear::ClearFilter;
+use crate::error::AppError;
+use crate::names::{Cursor, Direction, NameFilter, NameRecord, Sort};
+use crate::stats::{Bucket, BucketCounter, Granularity};
+use crate::transfer::{ItemRow, Record, VisitRow};
+
+/// Names kept in process memory and lost on exit, for tests and quick
+/// local runs.
+#[derive(Default)]
+pub struct MemoryStore {
+    data: Mutex<Data>,
+}
+
+#[derive(Default)]
+struct Data {
+    items: BTreeMap<String, Item>,
+    visits: Vec<VisitRow>,
+    /// API keys with their hashes, in order of creation.
+    keys: Vec<(KeyRecord, Vec<u8>)>,
+}
+
+#[derive(Clone)]
+struct Item {
+    count: i32,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    previous_seen: Option<DateTime<Utc>>,
+}
+
+impl Item {
+    fn record(&self, name: &str) -> NameRecord {
+        NameRecord {
+            name: name.to_string(),
+            count: self.count,
+            first_seen: self.first_seen,
+            last_seen: self.last_seen,
+        }
+    }
+
+    fn row(&self, name: &str) -> ItemRow {
+        ItemRow {
+            name: name.to_string(),
+            count: self.count,
+            first_seen: Some(self.first_seen),
+            last_seen: self.last_seen,
+            previous_seen: self.previous_seen,
+        }
+    }
+}
+
+impl MemoryStore {
+    fn data(&self) -> MutexGuard<'_, Data> {
+        // The data is only changed once a whole operation succeeded, so it
+        // is consistent even if a panic poisoned the lock.
+        self.data
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Data {
+    /// Names of the items matched by `filter`.
+    fn matching(&self, filter: &ClearFilter) -> BTreeSet<String> {
+        self.items
+            .iter()
+            .filter(|(name, item)| {
+                filter.name.as_ref().is_none_or(|wanted| wanted == *name)
+                    && filter
+                        .seen_before
+                        .is_none_or(|before| item.last_seen < before)
+                    && filter.max_count.is_none_or(|max| item.count <= max)
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+}
+
+fn matches_list(filter: &NameFilter, name: &str, item: &Item) -> bool {
+    filter
+        .seen_since
+        .is_none_or(|since| item.last_seen >= since)
+        && filter.min_visits.is_none_or(|min| item.count >= min)
+        && filter
+            .name_prefix
+            .as_deref()
+            .is_none_or(|prefix| name.starts_with(prefix))
+}
+
+#[async_trait]
+impl NameStore for MemoryStore {
+    async fn record_visit(
+        &self,
+        name: &str,
+        context: &VisitContext,
+    ) -> Result<RecordedVisit, AppError> {
+        let now = now();
+        let mut data = self.data();
+
+        let item = data
+            .items
+            .entry(name.to_string())
+            .and_modify(|item| {
+                item.count += 1;
+                item.previous_seen = Some(item.last_seen);
+                item.last_seen = now;
+            })
+            .or_insert(Item {
+                count: 1,
+                first_seen: now,
+                last_seen: now,
+                previous_seen: None,
+            })
+            .clone();
+
+        data.visits.push(VisitRow {
+            name: name.to_string(),
+            visited_at: now,
+            client_ip: context.client_ip.clone(),
+            user_agent: context.user_agent.clone(),
+            referer: context.referer.clone(),
+        });
+
+        Ok(RecordedVisit {
+            name: name.to_string(),
+            count: item.count,
+            previous_seen: item.previous_seen,
+        })
+    }
+
+    async fn record_duplicate(
+        &self,
+        name: &str,
+        context: &VisitContext,
+    ) -> Result<Option<NameRecord>, AppError> {
+        let mut data = self.data();
+        let Some(record) = data.items.get(name).map(|item| item.record(name)) else {
+            return Ok(None);
+        };
+
+        data.visits.push(VisitRow {
+            name: name.to_string(),
+            visited_at: now(),
+            client_ip: context.client_ip.clone(),
+            user_agent: context.user_agent.clone(),
+            referer: context.referer.clone(),
+        });
+        Ok(Some(record))
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<NameRecord>, AppError> {
+        Ok(self.data().items.get(name).map(|item| item.record(name)))
+    }
+
+    async fn list(
+        &self,
+        sort: Sort,
+        filter: &NameFilter,
+        limit: u32,
+        offset: u32,
+        after: Option<&Cursor>,
+    ) -> Result<Vec<NameRecord>, AppError> {
+        let mut records: Vec<NameRecord> = self
+            .data()
+            .items
+            .iter()
+            .filter(|(name, item)| matches_list(filter, name, item))
+            .map(|(name, item)| item.record(name))
+            .collect();
+
+        // Same position as the cursor encodes: the sort key, then the name.
+        let position = |record: &NameRecord| (sort.order.key(record), record.name.clone());
+        records.sort_by_cached_key(position);
+        if sort.direction == Direction::Desc {
+            records.reverse();
+        }
+        if let Some(cursor) = after {
+            let cursor = (cursor.key, cursor.name.clone());
+            records.retain(|record| match sort.direction {
+                Direction::Asc => position(record) > cursor,
+                Direction::Desc => position(record) < cursor,
+            });
+        }
+
+        Ok(records
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn count_names(&self) -> Result<i64, AppError> {
+        Ok(self.data().items.len() as i64)
+    }
+
+    async fn count(&self, filter: &ClearFilter) -> Result<(i64, i64), AppError> {
+        let data = self.data();
+        let names = data.matching(filter);
+        let visits = data
+            .visits
+            .iter()
+            .filter(|visit| filter.is_empty() || names.contains(&visit.name))
+            .count();
+
+        Ok((names.len() as i64, visits as i64))
+    }
+
+    async fn clear(
+        &self,
+        filter: &ClearFilter,
+        dump: Option<&mut dyn Dump>,
+    ) -> Result<Cleared, AppError> {
+        let mut data = self.data();
+        let names = data.matching(filter);
+        let deleted_visit = |visit: &VisitRow| filter.is_empty() || names.contains(&visit.name);
+
+        if let Some(dump) = dump {
+            for visit in data.visits.iter().filter(|visit| deleted_visit(visit)) {
+                dump.write(Record::Visit(visit.clone()))?;
+            }
+            for name in &names {
+                dump.write(Record::Item(data.items[name].row(name)))?;
+            }
+            dump.finish()?;
+        }
+
+        let visits_before = data.visits.len();
+        data.items.retain(|name, _| !names.contains(name));
+        data.visits.retain(|visit| !deleted_visit(visit));
+        Ok(Cleared {
+            names: names.len() as u64,
+            visits: (visits_before - data.visits.len()) as u64,
+        })
+    }
+
+    async fn visit_stats(
+        &self,
+        name: &str,
+        granularity: Granularity,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<Vec<Bucket>, AppError> {
+        let mut counter = BucketCounter::new(granularity, since, until, tz);
+        let data = self.data();
+        data.visits
+            .iter()
+            .filter(|visit| visit.name == name && (since..until).contains(&visit.visited_at))
+            .for_each(|visit| counter.add(visit.visited_at));
+        Ok(counter.finish())
+    }
+
+    async fn insert_key(
+        &self,
+        name: &str,
+        prefix: &str,
+        key_hash: &[u8],
+        scopes: &[&str],
+    ) -> Result<i64, AppError> {
+        let mut data = self.data();
+        let id = data.keys.len() as i64 + 1;
+        let record = KeyRecord {
+            id,
+            name: name.to_string(),
+            prefix: prefix.to_string(),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            created_at: now(),
+            last_used_at: None,
+            revoked_at: None,
+        };
+        data.keys.push((record, key_hash.to_vec()));
+        Ok(id)
+    }
+
+    async fn use_key(&self, key_hash: &[u8]) -> Result<Option<Vec<String>>, AppError> {
+        let mut data = self.data();
+        let key = data
+            .keys
+            .iter_mut()
+            .map(|(record, hash)| (record, &*hash))
+            .find(|(record, hash)| *hash == key_hash && record.revoked_at.is_none());
+        Ok(key.map(|(record, _)| {
+            record.last_used_at = Some(now());
+            record.scopes.clone()
+        }))
+    }
+
+    async fn revoke_key(&self, id: i64) -> Result<bool, AppError> {
+        let mut data = self.data();
+        let key = data
+            .keys
+            .iter_mut()
+            .map(|(record, _)| record)
+            .find(|record| record.id == id && record.revoked_at.is_none());
+        Ok(key.map(|record| record.revoked_at = Some(now())).is_some())
+    }
+
+    async fn list_keys(&self) -> Result<Vec<KeyRecord>, AppError> {
+        Ok(self
+            .data()
+            .keys
+            .iter()
+            .map(|(record, _)| record.clone())
+            .collect())
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,503 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures::TryStreamExt;
+use sqlx::{PgPool, Postgres, QueryBuilder, Transaction};
+
+use super::{Cleared, Dump, NameStore, RecordedVisit, VisitContext};
+use crate::aliases;
+use crate::auth::KeyRecord;
+use crate::clear::ClearFilter;
+use crate::config::DatabaseConfig;
+use crate::db;
+use crate::error::AppError;
+use crate::monitoring;
+use crate::names::{Cursor, Direction, NameFilter, NameRecord, OrderBy, Sort};
+use crate::stats::{Bucket, Granularity};
+use crate::transfer::{ItemRow, Record, VisitRow};
+
+/// Names kept in Postgres, the backend for production deployments.
+pub struct PgStore {
+    db: PgPool,
+}
+
+impl PgStore {
+    pub fn new(db: PgPool) -> Self {
+        PgStore { db }
+    }
+
+    /// Open the pool, see [`db::connect`]. Migrations are left to the caller.
+    pub async fn connect(url: &str, config: &DatabaseConfig) -> Result<Self, AppError> {
+        Ok(PgStore::new(db::connect(url, config).await?))
+    }
+}
+
+/// Build the listing query for any combination of sort, filters and position.
+fn list_query<'a>(
+    sort: Sort,
+    filter: &'a NameFilter,
+    limit: u32,
+    offset: u32,
+    after: Option<&'a Cursor>,
+) -> QueryBuilder<'a, Postgres> {
+    let mut query =
+        QueryBuilder::new("SELECT name, count, first_seen, last_seen FROM items WHERE TRUE");
+
+    if let Some(since) = filter.seen_since {
+        query.push(" AND last_seen >= ").push_bind(since);
+    }
+    if let Some(min_visits) = filter.min_visits {
+        query.push(" AND count >= ").push_bind(min_visits);
+    }
+    if let Some(prefix) = &filter.name_prefix {
+        query
+            .push(" AND starts_with(name, ")
+            .push_bind(prefix)
+            .push(")");
+    }
+
+    let column = sort.order.column();
+    let direction = sort.direction.as_sql();
+
+    if let Some(cursor) = after {
+        let comparison = match sort.direction {
+            Direction::Asc => ">",
+            Direction::Desc => "<",
+        };
+        match sort.order {
+            OrderBy::Name => {
+                query.push(format!(" AND name {comparison} "));
+            }
+            OrderBy::Visits => {
+                query
+                    .push(format!(" AND (count, name) {comparison} ("))
+                    .push_bind(cursor.key)
+                    .push("::bigint, ");
+            }
+            OrderBy::LastSeen | OrderBy::FirstSeen => {
+                query
+                    .push(format!(
+                        " AND ({column}, name) {comparison} (TIMESTAMPTZ 'epoch' + "
+                    ))
+                    .push_bind(cursor.key)
+                    .push("::bigint * INTERVAL '1 microsecond', ");
+            }
+        }
+        query.push_bind(&cursor.name);
+        if sort.order != OrderBy::Name {
+            query.push(")");
+        }
+    }
+
+    query.push(format!(" ORDER BY {column} {direction}"));
+    if sort.order != OrderBy::Name {
+        query.push(format!(", name {direction}"));
+    }
+    query
+        .push(" LIMIT ")
+        .push_bind(i64::from(limit))
+        .push(" OFFSET ")
+        .push_bind(i64::from(offset));
+
+    query
+}
+
+#[async_trait]
+impl NameStore for PgStore {
+    #[tracing::instrument(skip(self, context))]
+    async fn record_visit(
+        &self,
+        name: &str,
+        context: &VisitContext,
+    ) -> Result<RecordedVisit, AppError> {
+        // A single statement resolves aliases, bumps the counter and logs the
+        // visit. The DO UPDATE branch reads the locked, latest version of the row,
+        // so the reported previous state stays consistent under concurrent requests.
+        let mut conn = monitoring::acquire(&self.db).await?;
+        let record = sqlx::query!(
+            r#"WITH target AS (
+                   SELECT COALESCE((SELECT name FROM aliases WHERE alias = $1), $1) AS name
+               ),
+               upsert AS (
+                   INSERT INTO items (name, count, first_seen, last_seen)
+                   SELECT name, 1, NOW(), NOW() FROM target
+                   ON CONFLICT (name) DO UPDATE SET
+                   count = items.count + 1,
+                   previous_seen = items.last_seen,
+                   last_seen = NOW()
+                   RETURNING name, count, previous_seen
+               ),
+               visit AS (
+                   INSERT INTO visits (name, visited_at, client_ip, user_agent, referer)
+                   SELECT name, NOW(), $2, $3, $4 FROM target
+               )
+               SELECT name AS "name!", count AS "count!", previous_seen FROM upsert"#,
+            name,
+            context.client_ip,
+            context.user_agent,
+            context.referer
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        Ok(RecordedVisit {
+            name: record.name,
+            count: record.count,
+            previous_seen: record.previous_seen,
+        })
+    }
+
+    async fn record_duplicate(
+        &self,
+        name: &str,
+        context: &VisitContext,
+    ) -> Result<Option<NameRecord>, AppError> {
+        let mut conn = monitoring::acquire(&self.db).await?;
+        let record = sqlx::query_as!(
+            NameRecord,
+            r#"WITH item AS (
+                   SELECT name, count, first_seen, last_seen FROM items WHERE name = $1
+               ),
+               visit AS (
+                   INSERT INTO visits (name, visited_at, client_ip, user_agent, referer)
+                   SELECT name, NOW(), $2, $3, $4 FROM item
+               )
+               SELECT name AS "name!", count AS "count!",
+                      first_seen AS "first_seen!", last_seen AS "last_seen!"
+               FROM item"#,
+            name,
+            context.client_ip,
+            context.user_agent,
+            context.referer
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+        Ok(record)
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<NameRecord>, AppError> {
+        let mut conn = monitoring::acquire(&self.db).await?;
+        let record = sqlx::query_as!(
+            NameRecord,
+            "SELECT name, count, first_seen, last_seen FROM items WHERE name = $1",
+            name
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+        Ok(record)
+    }
+
+    async fn list(
+        &self,
+        sort: Sort,
+        filter: &NameFilter,
+        limit: u32,
+        offset: u32,
+        after: Option<&Cursor>,
+    ) -> Result<Vec<NameRecord>, AppError> {
+        let mut conn = monitoring::acquire(&self.db).await?;
+        let records = list_query(sort, filter, limit, offset, after)
+            .build_query_as()
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(records)
+    }
+
+    async fn count_names(&self) -> Result<i64, AppError> {
+        let mut conn = monitoring::acquire(&self.db).await?;
+        let count = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM items"#)
+            .fetch_one(&mut *conn)
+            .await?;
+        Ok(count)
+    }
+
+    async fn count(&self, filter: &ClearFilter) -> Result<(i64, i64), AppError> {
+        let mut conn = monitoring::acquire(&self.db).await?;
+        let counts = sqlx::query!(
+            r#"WITH matching AS (
+                   SELECT name FROM items
+                   WHERE ($1::text IS NULL OR name = $1)
+                     AND ($2::timestamptz IS NULL OR last_seen < $2)
+                     AND ($3::int IS NULL OR count <= $3)
+               )
+               SELECT
+                   (SELECT COUNT(*) FROM matching) AS "names!",
+                   (SELECT COUNT(*) FROM visits
+                    WHERE $4 OR name IN (SELECT name FROM matching)) AS "visits!""#,
+            filter.name,
+            filter.seen_before,
+            filter.max_count,
+            filter.is_empty()
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        Ok((counts.names, counts.visits))
+    }
+
+    async fn clear(
+        &self,
+        filter: &ClearFilter,
+        dump: Option<&mut dyn Dump>,
+    ) -> Result<Cleared, AppError> {
+        let mut tx = monitoring::begin(&self.db).await?;
+
+        let cleared = if filter.is_empty() && dump.is_none() {
+            // Nothing needs the rows back, so both tables are emptied at
+            // once, counting them under the same lock.
+            sqlx::query!("LOCK TABLE items, visits IN ACCESS EXCLUSIVE MODE")
+                .execute(&mut *tx)
+                .await?;
+            let counts = sqlx::query!(
+                r#"SELECT
+                       (SELECT COUNT(*) FROM items) AS "names!",
+                       (SELECT COUNT(*) FROM visits) AS "visits!""#
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            sqlx::query!("TRUNCATE TABLE items, visits")
+                .execute(&mut *tx)
+                .await?;
+            Cleared {
+                names: counts.names as u64,
+                visits: counts.visits as u64,
+            }
+        } else {
+            // The matching names are locked first, so that a concurrent visit
+            // cannot take one of them out of the filter between both deletes.
+            sqlx::query!(
+                "SELECT COUNT(*) FROM (
+                     SELECT 1 FROM items
+                     WHERE ($1::text IS NULL OR name = $1)
+                       AND ($2::timestamptz IS NULL OR last_seen < $2)
+                       AND ($3::int IS NULL OR count <= $3)
+                     FOR UPDATE
+                 ) locked",
+                filter.name,
+                filter.seen_before,
+                filter.max_count
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+
+            match dump {
+                Some(dump) => clear_into(&mut tx, filter, dump).await?,
+                None => {
+                    // Visits go first, while the items they are matched
+                    // through still exist.
+                    let visits = sqlx::query!(
+                        "DELETE FROM visits
+                         WHERE $4 OR name IN (
+                             SELECT name FROM items
+                             WHERE ($1::text IS NULL OR name = $1)
+                               AND ($2::timestamptz IS NULL OR last_seen < $2)
+                               AND ($3::int IS NULL OR count <= $3)
+                         )",
+                        filter.name,
+                        filter.seen_before,
+                        filter.max_count,
+                        filter.is_empty()
+                    )
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+                    let names = sqlx::query!(
+                        "DELETE FROM items
+                         WHERE ($1::text IS NULL OR name = $1)
+                           AND ($2::timestamptz IS NULL OR last_seen < $2)
+                           AND ($3::int IS NULL OR count <= $3)",
+                        filter.name,
+                        filter.seen_before,
+                        filter.max_count
+                    )
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+                    Cleared { names, visits }
+                }
+            }
+        };
+        tx.commit().await?;
+
+        Ok(cleared)
+    }
+
+    async fn visit_stats(
+        &self,
+        name: &str,
+        granularity: Granularity,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<Vec<Bucket>, AppError> {
+        let mut conn = monitoring::acquire(&self.db).await?;
+        // Walk every bucket in the range so that periods without visits show
+        // up as explicit zeroes instead of gaps. Buckets are generated on
+        // local wall clock time so that days and weeks start at midnight in
+        // `tz`, even across daylight saving changes.
+        let rows = sqlx::query!(
+            r#"SELECT b.start AT TIME ZONE $5 AS "start!", COUNT(v.id) AS "visits!"
+               FROM generate_series(
+                   date_trunc($1::text, $3::timestamptz AT TIME ZONE $5),
+                   ($4::timestamptz - interval '1 microsecond') AT TIME ZONE $5,
+                   ('1 ' || $1::text)::interval
+               ) AS b(start)
+               LEFT JOIN visits v
+                   ON v.name = $2
+                   AND date_trunc($1::text, v.visited_at AT TIME ZONE $5) = b.start
+                   AND v.visited_at >= $3
+                   AND v.visited_at < $4
+               GROUP BY b.start
+               ORDER BY b.start"#,
+            granularity.as_sql(),
+            name,
+            since,
+            until,
+            tz.name()
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| Bucket {
+                start: row.start.with_timezone(&tz),
+                visits: row.visits,
+            })
+            .collect())
+    }
+
+    async fn insert_key(
+        &self,
+        name: &str,
+        prefix: &str,
+        key_hash: &[u8],
+        scopes: &[&str],
+    ) -> Result<i64, AppError> {
+        let mut conn = monitoring::acquire(&self.db).await?;
+        let id = sqlx::query_scalar!(
+            "INSERT INTO api_keys (name, prefix, key_hash, scopes) VALUES ($1, $2, $3, $4)
+             RETURNING id",
+            name,
+            prefix,
+            key_hash,
+            scopes as &[&str]
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(id)
+    }
+
+    async fn use_key(&self, key_hash: &[u8]) -> Result<Option<Vec<String>>, AppError> {
+        let mut conn = monitoring::acquire(&self.db).await?;
+        let scopes = sqlx::query_scalar!(
+            "UPDATE api_keys SET last_used_at = NOW()
+             WHERE key_hash = $1 AND revoked_at IS NULL
+             RETURNING scopes",
+            key_hash
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+        Ok(scopes)
+    }
+
+    async fn revoke_key(&self, id: i64) -> Result<bool, AppError> {
+        let mut conn = monitoring::acquire(&self.db).await?;
+        let revoked = sqlx::query!(
+            "UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL",
+            id
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+        Ok(revoked > 0)
+    }
+
+    async fn list_keys(&self) -> Result<Vec<KeyRecord>, AppError> {
+        let mut conn = monitoring::acquire(&self.db).await?;
+        let keys = sqlx::query_as!(
+            KeyRecord,
+            "SELECT id, name, prefix, scopes, created_at, last_used_at, revoked_at
+             FROM api_keys
+             ORDER BY id"
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        Ok(keys)
+    }
+
+    async fn resolve(&self, name: &str) -> Result<String, AppError> {
+        let mut conn = monitoring::acquire(&self.db).await?;
+        Ok(aliases::resolve(&mut *conn, name).await?)
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        let mut conn = monitoring::acquire(&self.db).await?;
+        sqlx::query("SELECT 1").execute(&mut *conn).await?;
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.db.close().await;
+    }
+
+    fn postgres(&self) -> Option<&PgPool> {
+        Some(&self.db)
+    }
+}
+
+/// [`PgStore::clear`] handing every deleted row to `dump` as it comes
+/// back from the database.
+async fn clear_into(
+    tx: &mut Transaction<'_, Postgres>,
+    filter: &ClearFilter,
+    dump: &mut dyn Dump,
+) -> Result<Cleared, AppError> {
+    let mut cleared = Cleared {
+        names: 0,
+        visits: 0,
+    };
+
+    let mut visits = sqlx::query_as!(
+        VisitRow,
+        "DELETE FROM visits
+         WHERE $4 OR name IN (
+             SELECT name FROM items
+             WHERE ($1::text IS NULL OR name = $1)
+               AND ($2::timestamptz IS NULL OR last_seen < $2)
+               AND ($3::int IS NULL OR count <= $3)
+         )
+         RETURNING name, visited_at, client_ip, user_agent, referer",
+        filter.name,
+        filter.seen_before,
+        filter.max_count,
+        filter.is_empty()
+    )
+    .fetch(&mut **tx);
+    while let Some(visit) = visits.try_next().await? {
+        dump.write(Record::Visit(visit))?;
+        cleared.visits += 1;
+    }
+    drop(visits);
+
+    let mut items = sqlx::query_as!(
+        ItemRow,
+        r#"DELETE FROM items
+           WHERE ($1::text IS NULL OR name = $1)
+             AND ($2::timestamptz IS NULL OR last_seen < $2)
+             AND ($3::int IS NULL OR count <= $3)
+           RETURNING name, count, first_seen AS "first_seen?", last_seen, previous_seen"#,
+        filter.name,
+        filter.seen_before,
+        filter.max_count
+    )
+    .fetch(&mut **tx);
+    while let Some(item) = items.try_next().await? {
+        dump.write(Record::Item(item))?;
+        cleared.names += 1;
+    }
+    drop(items);
+
+    dump.finish()?;
+    Ok(cleared)
+}
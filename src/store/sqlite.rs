@@ -0,0 +1,522 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures::TryStreamExt;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions};
+use sqlx::{FromRow, QueryBuilder, Sqlite};
+
+use super::{now, Cleared, Dump, NameStore, RecordedVisit, VisitContext};
+use crate::auth::KeyRecord;
+use crate::clear::ClearFilter;
+use crate::config::DatabaseConfig;
+use crate::error::AppError;
+use crate::names::{Cursor, Direction, NameFilter, NameRecord, OrderBy, Sort};
+use crate::stats::{Bucket, BucketCounter, Granularity};
+use crate::transfer::{ItemRow, Record, VisitRow};
+
+/// The SQLite schema, applied whenever a database is opened.
+static MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+/// Conditions selecting the items matched by a [`ClearFilter`], bound as
+/// `?1` to `?3`.
+const MATCHING_ITEMS: &str = "(?1 IS NULL OR name = ?1)
+    AND (?2 IS NULL OR last_seen < ?2)
+    AND (?3 IS NULL OR count <= ?3)";
+
+/// Names kept in a local SQLite file, or in memory with `sqlite::memory:`.
+pub struct SqliteStore {
+    db: SqlitePool,
+}
+
+/// A row of `items`, with timestamps in microseconds since the epoch.
+#[derive(FromRow)]
+struct ItemRecord {
+    name: String,
+    count: i32,
+    first_seen: i64,
+    last_seen: i64,
+    previous_seen: Option<i64>,
+}
+
+#[derive(FromRow)]
+struct VisitRecord {
+    name: String,
+    visited_at: i64,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+    referer: Option<String>,
+}
+
+/// A row of `api_keys`, with its scopes comma separated.
+#[derive(FromRow)]
+struct KeyRow {
+    id: i64,
+    name: String,
+    prefix: String,
+    scopes: String,
+    created_at: i64,
+    last_used_at: Option<i64>,
+    revoked_at: Option<i64>,
+}
+
+impl KeyRow {
+    fn into_record(self) -> Result<KeyRecord, AppError> {
+        Ok(KeyRecord {
+            id: self.id,
+            name: self.name,
+            prefix: self.prefix,
+            scopes: split_scopes(&self.scopes),
+            created_at: timestamp(self.created_at)?,
+            last_used_at: self.last_used_at.map(timestamp).transpose()?,
+            revoked_at: self.revoked_at.map(timestamp).transpose()?,
+        })
+    }
+}
+
+fn split_scopes(scopes: &str) -> Vec<String> {
+    scopes.split(',').map(str::to_string).collect()
+}
+
+fn timestamp(micros: i64) -> Result<DateTime<Utc>, AppError> {
+    DateTime::from_timestamp_micros(micros).ok_or_else(|| {
+        AppError::Database(sqlx::Error::Decode(
+            format!("timestamp out of range: {micros}").into(),
+        ))
+    })
+}
+
+impl ItemRecord {
+    fn into_name(self) -> Result<NameRecord, AppError> {
+        Ok(NameRecord {
+            name: self.name,
+            count: self.count,
+            first_seen: timestamp(self.first_seen)?,
+            last_seen: timestamp(self.last_seen)?,
+        })
+    }
+
+    fn into_row(self) -> Result<ItemRow, AppError> {
+        Ok(ItemRow {
+            name: self.name,
+            count: self.count,
+            first_seen: Some(timestamp(self.first_seen)?),
+            last_seen: timestamp(self.last_seen)?,
+            previous_seen: self.previous_seen.map(timestamp).transpose()?,
+        })
+    }
+}
+
+impl VisitRecord {
+    fn into_row(self) -> Result<VisitRow, AppError> {
+        Ok(VisitRow {
+            name: self.name,
+            visited_at: timestamp(self.visited_at)?,
+            client_ip: self.client_ip,
+            user_agent: self.user_agent,
+            referer: self.referer,
+        })
+    }
+}
+
+impl SqliteStore {
+    /// Open the database, creating the file if needed, and migrate it.
+    pub async fn connect(url: &str, config: &DatabaseConfig) -> Result<Self, AppError> {
+        let options = SqliteConnectOptions::from_str(url)
+            .map_err(|e| AppError::Config(format!("Invalid database URL: {e}")))?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+
+        let acquire_timeout = Duration::from_secs(config.acquire_timeout_secs);
+        let pool_options = if url.contains(":memory:") || url.contains("mode=memory") {
+            // An in-memory database only lives as long as one of its
+            // connections, and concurrent connections to it fail on table
+            // locks instead of waiting, so keep exactly one open.
+            SqlitePoolOptions::new()
+                .max_connections(1)
+                .min_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+        } else {
+            let idle_timeout = (config.idle_timeout_secs > 0)
+                .then(|| Duration::from_secs(config.idle_timeout_secs));
+            SqlitePoolOptions::new()
+                .max_connections(config.max_connections)
+                .min_connections(config.min_connections)
+                .idle_timeout(idle_timeout)
+        };
+
+        let db = pool_options
+            .acquire_timeout(acquire_timeout)
+            .connect_with(options)
+            .await?;
+        MIGRATOR.run(&db).await?;
+
+        Ok(SqliteStore { db })
+    }
+}
+
+/// Build the listing query, like its Postgres counterpart but on integer
+/// timestamps.
+fn list_query<'a>(
+    sort: Sort,
+    filter: &'a NameFilter,
+    limit: u32,
+    offset: u32,
+    after: Option<&'a Cursor>,
+) -> QueryBuilder<'a, Sqlite> {
+    let mut query = QueryBuilder::new(
+        "SELECT name, count, first_seen, last_seen, previous_seen FROM items WHERE TRUE",
+    );
+
+    if let Some(since) = filter.seen_since {
+        query
+            .push(" AND last_seen >= ")
+            .push_bind(since.timestamp_micros());
+    }
+    if let Some(min_visits) = filter.min_visits {
+        query.push(" AND count >= ").push_bind(min_visits);
+    }
+    if let Some(prefix) = &filter.name_prefix {
+        query
+            .push(" AND substr(name, 1, length(")
+            .push_bind(prefix)
+            .push(")) = ")
+            .push_bind(prefix);
+    }
+
+    let column = sort.order.column();
+    let direction = sort.direction.as_sql();
+
+    if let Some(cursor) = after {
+        let comparison = match sort.direction {
+            Direction::Asc => ">",
+            Direction::Desc => "<",
+        };
+        if sort.order == OrderBy::Name {
+            query.push(format!(" AND name {comparison} "));
+        } else {
+            query
+                .push(format!(" AND ({column}, name) {comparison} ("))
+                .push_bind(cursor.key)
+                .push(", ");
+        }
+        query.push_bind(&cursor.name);
+        if sort.order != OrderBy::Name {
+            query.push(")");
+        }
+    }
+
+    query.push(format!(" ORDER BY {column} {direction}"));
+    if sort.order != OrderBy::Name {
+        query.push(format!(", name {direction}"));
+    }
+    query
+        .push(" LIMIT ")
+        .push_bind(i64::from(limit))
+        .push(" OFFSET ")
+        .push_bind(i64::from(offset));
+
+    query
+}
+
+#[async_trait]
+impl NameStore for SqliteStore {
+    async fn record_visit(
+        &self,
+        name: &str,
+        context: &VisitContext,
+    ) -> Result<RecordedVisit, AppError> {
+        let now = now().timestamp_micros();
+        // Writing first takes the database lock straight away, so concurrent
+        // visits queue up rather than fail to upgrade a read lock.
+        let mut tx = self.db.begin().await?;
+
+        let (name, count, previous_seen): (String, i32, Option<i64>) = sqlx::query_as(
+            "INSERT INTO items (name, count, first_seen, last_seen) VALUES (?1, 1, ?2, ?2)
+             ON CONFLICT (name) DO UPDATE SET
+             count = count + 1,
+             previous_seen = last_seen,
+             last_seen = excluded.last_seen
+             RETURNING name, count, previous_seen",
+        )
+        .bind(name)
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO visits (name, visited_at, client_ip, user_agent, referer)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )
+        .bind(&name)
+        .bind(now)
+        .bind(&context.client_ip)
+        .bind(&context.user_agent)
+        .bind(&context.referer)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(RecordedVisit {
+            name,
+            count,
+            previous_seen: previous_seen.map(timestamp).transpose()?,
+        })
+    }
+
+    async fn record_duplicate(
+        &self,
+        name: &str,
+        context: &VisitContext,
+    ) -> Result<Option<NameRecord>, AppError> {
+        // Written first for the same reason as in `record_visit`.
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(
+            "INSERT INTO visits (name, visited_at, client_ip, user_agent, referer)
+             SELECT name, ?2, ?3, ?4, ?5 FROM items WHERE name = ?1",
+        )
+        .bind(name)
+        .bind(now().timestamp_micros())
+        .bind(&context.client_ip)
+        .bind(&context.user_agent)
+        .bind(&context.referer)
+        .execute(&mut *tx)
+        .await?;
+
+        let record = sqlx::query_as::<_, ItemRecord>(
+            "SELECT name, count, first_seen, last_seen, previous_seen FROM items WHERE name = ?1",
+        )
+        .bind(name)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        record.map(ItemRecord::into_name).transpose()
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<NameRecord>, AppError> {
+        sqlx::query_as::<_, ItemRecord>(
+            "SELECT name, count, first_seen, last_seen, previous_seen FROM items WHERE name = ?1",
+        )
+        .bind(name)
+        .fetch_optional(&self.db)
+        .await?
+        .map(ItemRecord::into_name)
+        .transpose()
+    }
+
+    async fn list(
+        &self,
+        sort: Sort,
+        filter: &NameFilter,
+        limit: u32,
+        offset: u32,
+        after: Option<&Cursor>,
+    ) -> Result<Vec<NameRecord>, AppError> {
+        list_query(sort, filter, limit, offset, after)
+            .build_query_as::<ItemRecord>()
+            .fetch_all(&self.db)
+            .await?
+            .into_iter()
+            .map(ItemRecord::into_name)
+            .collect()
+    }
+
+    async fn count_names(&self) -> Result<i64, AppError> {
+        let count = sqlx::query_scalar("SELECT COUNT(*) FROM items")
+            .fetch_one(&self.db)
+            .await?;
+        Ok(count)
+    }
+
+    async fn count(&self, filter: &ClearFilter) -> Result<(i64, i64), AppError> {
+        let counts = sqlx::query_as(&format!(
+            "SELECT
+                 (SELECT COUNT(*) FROM items WHERE {MATCHING_ITEMS}),
+                 (SELECT COUNT(*) FROM visits
+                  WHERE ?4 OR name IN (SELECT name FROM items WHERE {MATCHING_ITEMS}))"
+        ))
+        .bind(&filter.name)
+        .bind(filter.seen_before.map(|before| before.timestamp_micros()))
+        .bind(filter.max_count)
+        .bind(filter.is_empty())
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(counts)
+    }
+
+    async fn clear(
+        &self,
+        filter: &ClearFilter,
+        dump: Option<&mut dyn Dump>,
+    ) -> Result<Cleared, AppError> {
+        let seen_before = filter.seen_before.map(|before| before.timestamp_micros());
+        // Visits go first, while the items they are matched through still exist.
+        let delete_visits = format!(
+            "DELETE FROM visits
+             WHERE ?4 OR name IN (SELECT name FROM items WHERE {MATCHING_ITEMS})"
+        );
+        let delete_items = format!("DELETE FROM items WHERE {MATCHING_ITEMS}");
+        let mut tx = self.db.begin().await?;
+
+        let cleared = match dump {
+            Some(dump) => {
+                let returning_visits = format!(
+                    "{delete_visits} RETURNING name, visited_at, client_ip, user_agent, referer"
+                );
+                let mut visits = sqlx::query_as::<_, VisitRecord>(&returning_visits)
+                    .bind(&filter.name)
+                    .bind(seen_before)
+                    .bind(filter.max_count)
+                    .bind(filter.is_empty())
+                    .fetch(&mut *tx);
+                let mut visit_count = 0;
+                while let Some(visit) = visits.try_next().await? {
+                    dump.write(Record::Visit(visit.into_row()?))?;
+                    visit_count += 1;
+                }
+                drop(visits);
+
+                let returning_items = format!(
+                    "{delete_items} RETURNING name, count, first_seen, last_seen, previous_seen"
+                );
+                let mut items = sqlx::query_as::<_, ItemRecord>(&returning_items)
+                    .bind(&filter.name)
+                    .bind(seen_before)
+                    .bind(filter.max_count)
+                    .fetch(&mut *tx);
+                let mut name_count = 0;
+                while let Some(item) = items.try_next().await? {
+                    dump.write(Record::Item(item.into_row()?))?;
+                    name_count += 1;
+                }
+                drop(items);
+
+                dump.finish()?;
+                Cleared {
+                    names: name_count,
+                    visits: visit_count,
+                }
+            }
+            None => {
+                let visits = sqlx::query(&delete_visits)
+                    .bind(&filter.name)
+                    .bind(seen_before)
+                    .bind(filter.max_count)
+                    .bind(filter.is_empty())
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+                let names = sqlx::query(&delete_items)
+                    .bind(&filter.name)
+                    .bind(seen_before)
+                    .bind(filter.max_count)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+                Cleared { names, visits }
+            }
+        };
+        tx.commit().await?;
+
+        Ok(cleared)
+    }
+
+    async fn visit_stats(
+        &self,
+        name: &str,
+        granularity: Granularity,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<Vec<Bucket>, AppError> {
+        let mut counter = BucketCounter::new(granularity, since, until, tz);
+        let mut visits = sqlx::query_scalar::<_, i64>(
+            "SELECT visited_at FROM visits
+             WHERE name = ?1 AND visited_at >= ?2 AND visited_at < ?3",
+        )
+        .bind(name)
+        .bind(since.timestamp_micros())
+        .bind(until.timestamp_micros())
+        .fetch(&self.db);
+        while let Some(visited_at) = visits.try_next().await? {
+            counter.add(timestamp(visited_at)?);
+        }
+        Ok(counter.finish())
+    }
+
+    async fn insert_key(
+        &self,
+        name: &str,
+        prefix: &str,
+        key_hash: &[u8],
+        scopes: &[&str],
+    ) -> Result<i64, AppError> {
+        let id = sqlx::query_scalar(
+            "INSERT INTO api_keys (name, prefix, key_hash, scopes, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             RETURNING id",
+        )
+        .bind(name)
+        .bind(prefix)
+        .bind(key_hash)
+        .bind(scopes.join(","))
+        .bind(now().timestamp_micros())
+        .fetch_one(&self.db)
+        .await?;
+        Ok(id)
+    }
+
+    async fn use_key(&self, key_hash: &[u8]) -> Result<Option<Vec<String>>, AppError> {
+        let scopes: Option<String> = sqlx::query_scalar(
+            "UPDATE api_keys SET last_used_at = ?2
+             WHERE key_hash = ?1 AND revoked_at IS NULL
+             RETURNING scopes",
+        )
+        .bind(key_hash)
+        .bind(now().timestamp_micros())
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(scopes.as_deref().map(split_scopes))
+    }
+
+    async fn revoke_key(&self, id: i64) -> Result<bool, AppError> {
+        let revoked =
+            sqlx::query("UPDATE api_keys SET revoked_at = ?2 WHERE id = ?1 AND revoked_at IS NULL")
+                .bind(id)
+                .bind(now().timestamp_micros())
+                .execute(&self.db)
+                .await?
+                .rows_affected();
+        Ok(revoked > 0)
+    }
+
+    async fn list_keys(&self) -> Result<Vec<KeyRecord>, AppError> {
+        sqlx::query_as::<_, KeyRow>(
+            "SELECT id, name, prefix, scopes, created_at, last_used_at, revoked_at
+             FROM api_keys
+             ORDER BY id",
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(KeyRow::into_record)
+        .collect()
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        sqlx::query("SELECT 1").execute(&self.db).await?;
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.db.close().await;
+    }
+}
@@ -0,0 +1,65 @@
+use axum::http::Request;
+use tower_http::{
+    classify::{ServerErrorsAsFailures, SharedClassifier},
+    request_id::RequestId,
+    trace::{DefaultOnFailure, DefaultOnResponse, MakeSpan, TraceLayer},
+};
+use tracing::{Level, Span};
+use tracing_subscriber::EnvFilter;
+
+use crate::config::{LogConfig, LogFormat};
+use crate::error::AppError;
+
+/// Install the global tracing subscriber.
+///
+/// Logs go to stderr so that command output on stdout stays pipeable. The
+/// format is `pretty`, human readable, or `json`, and the verbosity is set
+/// with the usual `RUST_LOG` directives.
+pub fn init(config: &LogConfig) -> Result<(), AppError> {
+    let filter = EnvFilter::try_new(&config.filter)
+        .map_err(|e| AppError::Config(format!("Invalid log filter: {e}")))?;
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+
+    match config.format {
+        LogFormat::Pretty => builder.init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .init(),
+    }
+
+    Ok(())
+}
+
+/// Opens one span per request, tagged with its `x-request-id`.
+#[derive(Clone)]
+pub struct RequestSpan;
+
+impl<B> MakeSpan<B> for RequestSpan {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .and_then(|id| id.header_value().to_str().ok())
+            .unwrap_or_default();
+
+        tracing::info_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            request_id,
+        )
+    }
+}
+
+pub fn trace_layer() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>, RequestSpan> {
+    TraceLayer::new_for_http()
+        .make_span_with(RequestSpan)
+        .on_response(DefaultOnResponse::new().level(Level::INFO))
+        .on_failure(DefaultOnFailure::new().level(Level::ERROR))
+}
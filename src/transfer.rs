@@ -0,0 +1,607 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use futures::{SinkExt, Stream, TryStreamExt};
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::{PgConnection, PgPool};
+use tokio::sync::mpsc;
+
+use crate::error::AppError;
+use crate::monitoring;
+use crate::store::{require_postgres, Store};
+use crate::validate::Query;
+
+/// Rows written to the database in one statement during an import.
+const BATCH_SIZE: usize = 1_000;
+
+/// Rows of an export read ahead of the writer.
+const EXPORT_READ_AHEAD: usize = 256;
+
+/// Bytes of an HTTP export gathered before they are sent to the client.
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(ValueEnum, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// One row per line, with a `table` column telling items and visits apart
+    Csv,
+    /// A single array of objects
+    Json,
+    /// One object per line
+    Ndjson,
+}
+
+impl Format {
+    /// Guess the format of a file from its extension, defaulting to NDJSON.
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => Format::Csv,
+            Some("json") => Format::Json,
+            _ => Format::Ndjson,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv",
+            Format::Json => "application/json",
+            Format::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Add to the existing data, summing the counts of names present in both
+    Merge,
+    /// Delete all names and visits first
+    Replace,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ItemRow {
+    pub name: String,
+    pub count: i32,
+    /// Missing from exports made before first_seen was tracked.
+    #[serde(default)]
+    pub first_seen: Option<DateTime<Utc>>,
+    pub last_seen: DateTime<Utc>,
+    pub previous_seen: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct VisitRow {
+    pub name: String,
+    pub visited_at: DateTime<Utc>,
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+}
+
+/// One exported row, tagged with the table it belongs to.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "table", rename_all = "snake_case")]
+pub enum Record {
+    Item(ItemRow),
+    Visit(VisitRow),
+}
+
+/// CSV has no nesting, so both tables share one set of columns and each row
+/// leaves the other table's columns empty.
+#[derive(Serialize, Deserialize)]
+struct CsvRow {
+    table: String,
+    name: String,
+    count: Option<i32>,
+    first_seen: Option<DateTime<Utc>>,
+    last_seen: Option<DateTime<Utc>>,
+    previous_seen: Option<DateTime<Utc>>,
+    visited_at: Option<DateTime<Utc>>,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+    referer: Option<String>,
+}
+
+impl From<Record> for CsvRow {
+    fn from(record: Record) -> Self {
+        match record {
+            Record::Item(item) => CsvRow {
+                table: "item".to_string(),
+                name: item.name,
+                count: Some(item.count),
+                first_seen: item.first_seen,
+                last_seen: Some(item.last_seen),
+                previous_seen: item.previous_seen,
+                visited_at: None,
+                client_ip: None,
+                user_agent: None,
+                referer: None,
+            },
+            Record::Visit(visit) => CsvRow {
+                table: "visit".to_string(),
+                name: visit.name,
+                count: None,
+                first_seen: None,
+                last_seen: None,
+                previous_seen: None,
+                visited_at: Some(visit.visited_at),
+                client_ip: visit.client_ip,
+                user_agent: visit.user_agent,
+                referer: visit.referer,
+            },
+        }
+    }
+}
+
+impl TryFrom<CsvRow> for Record {
+    type Error = String;
+
+    fn try_from(row: CsvRow) -> Result<Self, Self::Error> {
+        match row.table.as_str() {
+            "item" => Ok(Record::Item(ItemRow {
+                name: row.name,
+                count: row.count.ok_or("item without a count")?,
+                first_seen: row.first_seen,
+                last_seen: row.last_seen.ok_or("item without a last_seen")?,
+                previous_seen: row.previous_seen,
+            })),
+            "visit" => Ok(Record::Visit(VisitRow {
+                name: row.name,
+                visited_at: row.visited_at.ok_or("visit without a visited_at")?,
+                client_ip: row.client_ip,
+                user_agent: row.user_agent,
+                referer: row.referer,
+            })),
+            table => Err(format!("unknown table '{table}' (expected item or visit)")),
+        }
+    }
+}
+
+/// Serializes records one at a time in the chosen format.
+pub enum RecordWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Json { out: W, empty: bool },
+    Ndjson(W),
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(format: Format, out: W) -> Self {
+        match format {
+            Format::Csv => RecordWriter::Csv(Box::new(csv::Writer::from_writer(out))),
+            Format::Json => RecordWriter::Json { out, empty: true },
+            Format::Ndjson => RecordWriter::Ndjson(out),
+        }
+    }
+
+    pub fn write(&mut self, record: Record) -> Result<(), AppError> {
+        match self {
+            RecordWriter::Csv(writer) => writer
+                .serialize(CsvRow::from(record))
+                .map_err(io::Error::from)?,
+            RecordWriter::Json { out, empty } => {
+                out.write_all(if *empty { b"[\n" } else { b",\n" })?;
+                *empty = false;
+                serde_json::to_writer(&mut *out, &record).map_err(io::Error::from)?;
+            }
+            RecordWriter::Ndjson(out) => {
+                serde_json::to_writer(&mut *out, &record).map_err(io::Error::from)?;
+                out.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), AppError> {
+        match self {
+            RecordWriter::Csv(writer) => writer.flush()?,
+            RecordWriter::Json { out, empty } => {
+                out.write_all(if *empty { b"[]\n" } else { b"\n]\n" })?;
+                out.flush()?;
+            }
+            RecordWriter::Ndjson(out) => out.flush()?,
+        }
+        Ok(())
+    }
+}
+
+type RecordSender = futures::channel::mpsc::Sender<Result<Record, sqlx::Error>>;
+
+/// Every name, then every visit, streamed from Postgres.
+///
+/// Both tables are read on one connection, in a single `REPEATABLE READ`
+/// transaction, so that the visits agree with the counts of the names even
+/// while traffic is flowing. The transaction is held by a separate task,
+/// which gives up once the stream is dropped.
+pub fn records(db: &PgPool) -> impl Stream<Item = Result<Record, sqlx::Error>> + Send + use<> {
+    let db = db.clone();
+    let (mut sender, receiver) = futures::channel::mpsc::channel(EXPORT_READ_AHEAD);
+    tokio::spawn(async move {
+        if let Err(e) = send_records(&db, &mut sender).await {
+            let _ = sender.send(Err(e)).await;
+        }
+    });
+    receiver
+}
+
+/// Read both tables for [`records`], stopping early if nobody listens.
+async fn send_records(db: &PgPool, sender: &mut RecordSender) -> Result<(), sqlx::Error> {
+    let mut tx = monitoring::begin(db).await?;
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+
+    let mut items = sqlx::query_as!(
+        ItemRow,
+        r#"SELECT name, count, first_seen AS "first_seen?", last_seen, previous_seen
+           FROM items
+           ORDER BY name"#
+    )
+    .fetch(&mut *tx);
+    while let Some(item) = items.try_next().await? {
+        if sender.send(Ok(Record::Item(item))).await.is_err() {
+            return Ok(());
+        }
+    }
+    drop(items);
+
+    let mut visits = sqlx::query_as!(
+        VisitRow,
+        "SELECT name, visited_at, client_ip, user_agent, referer
+         FROM visits
+         ORDER BY visited_at, id"
+    )
+    .fetch(&mut *tx);
+    while let Some(visit) = visits.try_next().await? {
+        if sender.send(Ok(Record::Visit(visit))).await.is_err() {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Write every name, then every visit, to `output` or stdout.
+///
+/// Rows are streamed from Postgres straight to the output, so exports do
+/// not need to fit in memory.
+pub async fn export(db: &PgPool, format: Format, output: Option<&Path>) -> Result<(), AppError> {
+    let out: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut writer = RecordWriter::new(format, BufWriter::new(out));
+
+    let (mut item_count, mut visit_count) = (0, 0);
+    let mut records = pin!(records(db));
+    while let Some(record) = records.try_next().await? {
+        match record {
+            Record::Item(_) => item_count += 1,
+            Record::Visit(_) => visit_count += 1,
+        }
+        writer.write(record)?;
+    }
+
+    writer.finish()?;
+    // stdout may be the export itself.
+    eprintln!("Exported {item_count} names and {visit_count} visits");
+    Ok(())
+}
+
+/// Bytes written by a [`RecordWriter`], taken out in chunks for a response body.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+
+    fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    format: Option<Format>,
+}
+
+/// `GET /export`: the same output as `blort export`, NDJSON unless `format`
+/// says otherwise.
+///
+/// The body is written by a separate task, [`EXPORT_CHUNK_SIZE`] bytes at a
+/// time, so that slow clients hold back the query instead of filling memory.
+pub async fn export_handler(
+    Query(query): Query<ExportQuery>,
+    State(store): State<Store>,
+) -> Result<Response, AppError> {
+    let db = require_postgres(&*store, "Exporting")?.clone();
+    let format = query.format.unwrap_or(Format::Ndjson);
+    let (mut sender, receiver) = futures::channel::mpsc::channel::<io::Result<Vec<u8>>>(1);
+
+    tokio::spawn(async move {
+        let buffer = SharedBuffer::default();
+        let mut writer = RecordWriter::new(format, buffer.clone());
+        let result: Result<(), AppError> = async {
+            let mut records = pin!(records(&db));
+            while let Some(record) = records.try_next().await? {
+                writer.write(record)?;
+                if buffer.len() >= EXPORT_CHUNK_SIZE
+                    && sender.send(Ok(buffer.take())).await.is_err()
+                {
+                    // The client went away.
+                    return Ok(());
+                }
+            }
+            writer.finish()?;
+            let _ = sender.send(Ok(buffer.take())).await;
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            tracing::error!(error = %e, "Export failed");
+            let _ = sender.send(Err(io::Error::other(e.to_string()))).await;
+        }
+    });
+
+    Ok((
+        [(header::CONTENT_TYPE, format.content_type())],
+        Body::from_stream(receiver),
+    )
+        .into_response())
+}
+
+type Parsed = Result<Record, String>;
+
+/// Sends each element of a JSON array down the channel as soon as it is parsed.
+struct RecordSeq<'a>(&'a mpsc::Sender<Parsed>);
+
+impl<'de> Visitor<'de> for RecordSeq<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array of records")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(record) = seq.next_element()? {
+            if self.0.blocking_send(Ok(record)).is_err() {
+                return Err(de::Error::custom("import aborted"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse `reader`, sending records down `sender` as they are read. Runs on a
+/// blocking thread; stops at the first error, which is sent as well.
+fn parse(format: Format, reader: impl BufRead, sender: &mpsc::Sender<Parsed>) {
+    let result = match format {
+        Format::Ndjson => (|| {
+            for (number, line) in reader.lines().enumerate() {
+                let line = line.map_err(|e| e.to_string())?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record =
+                    serde_json::from_str(&line).map_err(|e| format!("line {}: {e}", number + 1))?;
+                if sender.blocking_send(Ok(record)).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })(),
+        Format::Json => serde_json::Deserializer::from_reader(reader)
+            .deserialize_seq(RecordSeq(sender))
+            .map_err(|e| e.to_string()),
+        Format::Csv => (|| {
+            let mut csv = csv::Reader::from_reader(reader);
+            for (number, row) in csv.deserialize::<CsvRow>().enumerate() {
+                let row = row.map_err(|e| e.to_string())?;
+                // Numbered from 1, after the header line.
+                let record =
+                    Record::try_from(row).map_err(|e| format!("line {}: {e}", number + 2))?;
+                if sender.blocking_send(Ok(record)).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })(),
+    };
+
+    if let Err(e) = result {
+        let _ = sender.blocking_send(Err(e));
+    }
+}
+
+/// Rows waiting to be written, flushed every [`BATCH_SIZE`] rows.
+#[derive(Default)]
+struct Batch {
+    items: Vec<ItemRow>,
+    visits: Vec<VisitRow>,
+}
+
+/// Upsert a batch of names. Names that already exist, or that appear more
+/// than once in the batch, are combined as `blort merge` does: counts are
+/// summed, and the earliest first_seen and latest last_seen are kept.
+async fn insert_items(conn: &mut PgConnection, items: &[ItemRow]) -> Result<(), sqlx::Error> {
+    let names: Vec<&str> = items.iter().map(|item| item.name.as_str()).collect();
+    let counts: Vec<i32> = items.iter().map(|item| item.count).collect();
+    let first_seen: Vec<Option<DateTime<Utc>>> = items.iter().map(|item| item.first_seen).collect();
+    let last_seen: Vec<DateTime<Utc>> = items.iter().map(|item| item.last_seen).collect();
+    let previous_seen: Vec<Option<DateTime<Utc>>> =
+        items.iter().map(|item| item.previous_seen).collect();
+
+    // Imported names may be aliases in this database: count them under the
+    // canonical name, as live visits would be.
+    sqlx::query!(
+        r#"INSERT INTO items (name, count, first_seen, last_seen, previous_seen)
+           SELECT
+               COALESCE(aliases.name, row.name),
+               SUM(row.count),
+               MIN(COALESCE(row.first_seen, row.previous_seen, row.last_seen)),
+               MAX(row.last_seen),
+               GREATEST(
+                   MAX(row.previous_seen),
+                   (ARRAY_AGG(row.last_seen ORDER BY row.last_seen DESC))[2]
+               )
+           FROM UNNEST(
+               $1::text[], $2::int[], $3::timestamptz[], $4::timestamptz[], $5::timestamptz[]
+           ) AS row(name, count, first_seen, last_seen, previous_seen)
+           LEFT JOIN aliases ON aliases.alias = row.name
+           GROUP BY COALESCE(aliases.name, row.name)
+           ON CONFLICT (name) DO UPDATE SET
+           count = items.count + EXCLUDED.count,
+           first_seen = LEAST(items.first_seen, EXCLUDED.first_seen),
+           previous_seen = GREATEST(
+               LEAST(items.last_seen, EXCLUDED.last_seen),
+               items.previous_seen,
+               EXCLUDED.previous_seen
+           ),
+           last_seen = GREATEST(items.last_seen, EXCLUDED.last_seen)"#,
+        &names as &[&str],
+        &counts,
+        &first_seen as &[Option<DateTime<Utc>>],
+        &last_seen,
+        &previous_seen as &[Option<DateTime<Utc>>]
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_visits(conn: &mut PgConnection, visits: &[VisitRow]) -> Result<(), sqlx::Error> {
+    let names: Vec<&str> = visits.iter().map(|visit| visit.name.as_str()).collect();
+    let visited_at: Vec<DateTime<Utc>> = visits.iter().map(|visit| visit.visited_at).collect();
+    let client_ips: Vec<Option<&str>> = visits
+        .iter()
+        .map(|visit| visit.client_ip.as_deref())
+        .collect();
+    let user_agents: Vec<Option<&str>> = visits
+        .iter()
+        .map(|visit| visit.user_agent.as_deref())
+        .collect();
+    let referers: Vec<Option<&str>> = visits
+        .iter()
+        .map(|visit| visit.referer.as_deref())
+        .collect();
+
+    sqlx::query!(
+        r#"INSERT INTO visits (name, visited_at, client_ip, user_agent, referer)
+           SELECT COALESCE(aliases.name, row.name), row.visited_at,
+                  row.client_ip, row.user_agent, row.referer
+           FROM UNNEST($1::text[], $2::timestamptz[], $3::text[], $4::text[], $5::text[])
+               AS row(name, visited_at, client_ip, user_agent, referer)
+           LEFT JOIN aliases ON aliases.alias = row.name"#,
+        &names as &[&str],
+        &visited_at,
+        &client_ips as &[Option<&str>],
+        &user_agents as &[Option<&str>],
+        &referers as &[Option<&str>]
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+impl Batch {
+    fn is_full(&self) -> bool {
+        self.items.len() >= BATCH_SIZE || self.visits.len() >= BATCH_SIZE
+    }
+
+    async fn flush(&mut self, conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+        if !self.items.is_empty() {
+            insert_items(conn, &self.items).await?;
+            self.items.clear();
+        }
+        if !self.visits.is_empty() {
+            insert_visits(conn, &self.visits).await?;
+            self.visits.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Load a file written by [`export`], or `-` for stdin.
+///
+/// The file is parsed on a separate thread and written in batches as it is
+/// read, all in one transaction: either every row is imported or none is.
+pub async fn import(
+    db: &PgPool,
+    path: &Path,
+    format: Option<Format>,
+    mode: ImportMode,
+) -> Result<(), AppError> {
+    let format = format.unwrap_or_else(|| Format::from_path(path));
+    let reader: Box<dyn BufRead + Send> = if path == Path::new("-") {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(path).map_err(|e| {
+            AppError::Invalid(format!("Cannot open '{}': {e}", path.display()))
+        })?))
+    };
+
+    let (sender, mut receiver) = mpsc::channel(BATCH_SIZE);
+    let parser = tokio::task::spawn_blocking(move || parse(format, reader, &sender));
+
+    let mut tx = monitoring::begin(db).await?;
+    if mode == ImportMode::Replace {
+        sqlx::query!("DELETE FROM items").execute(&mut *tx).await?;
+        sqlx::query!("DELETE FROM visits").execute(&mut *tx).await?;
+    }
+
+    let mut batch = Batch::default();
+    let (mut item_count, mut visit_count) = (0, 0);
+    while let Some(record) = receiver.recv().await {
+        match record
+            .map_err(|e| AppError::Invalid(format!("Cannot import '{}': {e}", path.display())))?
+        {
+            Record::Item(item) => {
+                batch.items.push(item);
+                item_count += 1;
+            }
+            Record::Visit(visit) => {
+                batch.visits.push(visit);
+                visit_count += 1;
+            }
+        }
+        if batch.is_full() {
+            batch.flush(&mut tx).await?;
+        }
+    }
+    batch.flush(&mut tx).await?;
+    parser
+        .await
+        .map_err(|e| AppError::Io(io::Error::other(e)))?;
+
+    tx.commit().await?;
+
+    println!("Imported {item_count} names and {visit_count} visits");
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+use axum::{
+    extract::{FromRef, FromRequestParts, Path},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::de::DeserializeOwned;
+use unicode_properties::{GeneralCategoryGroup, UnicodeGeneralCategory};
+
+use crate::config::NameConfig;
+use crate::error::AppError;
+use crate::normalize::Normalizer;
+
+/// Rules a name has to follow before it is counted.
+///
+/// Configured through the `[names]` section of the configuration:
+/// - `max_length`: maximum length in characters, after normalization (default 64)
+/// - `allowed_classes`: comma separated Unicode character classes among
+///   `letter`, `mark`, `number`, `punctuation`, `symbol` and `space`
+///   (default: all but `symbol`)
+/// - `denylist_file`: file with one forbidden name per line, `#` starts a comment
+///
+/// Control, format and unassigned code points, as well as line and paragraph
+/// separators, are always rejected.
+#[derive(Clone)]
+pub struct NamePolicy {
+    max_length: usize,
+    allowed: Vec<GeneralCategoryGroup>,
+    denylist: Arc<HashSet<String>>,
+}
+
+impl NamePolicy {
+    pub fn new(config: &NameConfig, normalizer: &Normalizer) -> Result<Self, AppError> {
+        let allowed = parse_classes(&config.allowed_classes)?;
+
+        // Denied names go through the same normalization as incoming names,
+        // so the file can be written without worrying about case or spacing.
+        let denylist = match &config.denylist_file {
+            Some(path) => std::fs::read_to_string(path)
+                .map_err(|e| {
+                    AppError::Config(format!(
+                        "Cannot read denylist file '{}': {e}",
+                        path.display()
+                    ))
+                })?
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or_default())
+                .map(|entry| normalizer.normalize(entry))
+                .filter(|entry| !entry.is_empty())
+                .collect(),
+            None => HashSet::new(),
+        };
+
+        Ok(NamePolicy {
+            max_length: config.max_length,
+            allowed,
+            denylist: Arc::new(denylist),
+        })
+    }
+
+    /// Check an already normalized name against the policy.
+    pub fn validate(&self, name: &str) -> Result<(), NameRejection> {
+        if name.trim().is_empty() {
+            return Err(NameRejection::Empty);
+        }
+
+        for c in name.chars() {
+            let group = c.general_category_group();
+            let is_line_break = c == '\u{2028}' || c == '\u{2029}';
+            if group == GeneralCategoryGroup::Other || is_line_break {
+                return Err(NameRejection::InvalidCharacter(c));
+            }
+            if !self.allowed.contains(&group) {
+                return Err(NameRejection::DisallowedCharacter(c));
+            }
+        }
+
+        let length = name.chars().count();
+        if length > self.max_length {
+            return Err(NameRejection::TooLong {
+                length,
+                max_length: self.max_length,
+            });
+        }
+
+        if self.denylist.contains(name) {
+            return Err(NameRejection::Denied);
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a comma separated list of character classes.
+pub fn parse_classes(classes: &str) -> Result<Vec<GeneralCategoryGroup>, AppError> {
+    classes
+        .split(',')
+        .map(str::trim)
+        .filter(|class| !class.is_empty())
+        .map(|class| match class {
+            "letter" => Ok(GeneralCategoryGroup::Letter),
+            "mark" => Ok(GeneralCategoryGroup::Mark),
+            "number" => Ok(GeneralCategoryGroup::Number),
+            "punctuation" => Ok(GeneralCategoryGroup::Punctuation),
+            "symbol" => Ok(GeneralCategoryGroup::Symbol),
+            "space" => Ok(GeneralCategoryGroup::Separator),
+            _ => Err(AppError::Config(format!(
+                "Unknown character class '{class}' \
+                 (expected letter, mark, number, punctuation, symbol or space)"
+            ))),
+        })
+        .collect()
+}
+
+/// Why a name was refused.
+#[derive(Debug)]
+pub enum NameRejection {
+    Empty,
+    InvalidCharacter(char),
+    DisallowedCharacter(char),
+    TooLong { length: usize, max_length: usize },
+    Denied,
+}
+
+impl NameRejection {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            NameRejection::Empty | NameRejection::InvalidCharacter(_) => StatusCode::BAD_REQUEST,
+            NameRejection::DisallowedCharacter(_)
+            | NameRejection::TooLong { .. }
+            | NameRejection::Denied => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    /// Machine-readable identifier sent as `error` in the response body.
+    pub fn code(&self) -> &'static str {
+        match self {
+            NameRejection::Empty => "empty_name",
+            NameRejection::InvalidCharacter(_) => "invalid_character",
+            NameRejection::DisallowedCharacter(_) => "disallowed_character",
+            NameRejection::TooLong { .. } => "name_too_long",
+            NameRejection::Denied => "name_denied",
+        }
+    }
+}
+
+impl fmt::Display for NameRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameRejection::Empty => f.write_str("Name is empty once normalized"),
+            NameRejection::InvalidCharacter(c) => {
+                write!(f, "Name contains the invalid character U+{:04X}", *c as u32)
+            }
+            NameRejection::DisallowedCharacter(c) => write!(
+                f,
+                "Name contains the disallowed character '{c}' (U+{:04X})",
+                *c as u32
+            ),
+            NameRejection::TooLong { length, max_length } => write!(
+                f,
+                "Name is {length} characters long, the maximum is {max_length}"
+            ),
+            NameRejection::Denied => f.write_str("This name is not allowed"),
+        }
+    }
+}
+
+/// The `{name}` path segment, normalized and checked against the [`NamePolicy`].
+pub struct ValidName(pub String);
+
+impl<S> FromRequestParts<S> for ValidName
+where
+    S: Send + Sync,
+    Normalizer: FromRef<S>,
+    NamePolicy: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(name) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let name = Normalizer::from_ref(state).normalize(&name);
+        NamePolicy::from_ref(state)
+            .validate(&name)
+            .map_err(|rejection| AppError::from(rejection).into_response())?;
+
+        Ok(ValidName(name))
+    }
+}
+
+/// Query string parameters, deserialized like axum's `Query` but rejected
+/// with the JSON error body of [`AppError::Invalid`].
+pub struct Query<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::extract::Query(query) = axum::extract::Query::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| AppError::Invalid(rejection.body_text()))?;
+        Ok(Query(query))
+    }
+}
@@ -0,0 +1,13 @@
+-- Four names with distinct timestamps, where alice and carol tie on visits.
+INSERT INTO items (name, count, first_seen, last_seen, previous_seen) VALUES
+    ('alice', 5, '2025-01-01 10:00:00+00', '2025-03-01 10:00:00+00', '2025-02-27 10:00:00+00'),
+    ('bob', 2, '2025-02-01 10:00:00+00', '2025-03-05 10:00:00+00', '2025-02-01 10:00:00+00'),
+    ('carol', 5, '2024-12-01 10:00:00+00', '2025-02-15 10:00:00+00', '2025-02-14 10:00:00+00'),
+    ('dave', 1, '2025-02-20 10:00:00+00', '2025-02-20 10:00:00+00', NULL);
+
+INSERT INTO visits (name, visited_at, client_ip) VALUES
+    ('alice', '2025-02-27 10:00:00+00', '192.0.2.1'),
+    ('alice', '2025-03-01 10:00:00+00', '192.0.2.1'),
+    ('bob', '2025-02-01 10:00:00+00', '192.0.2.2'),
+    ('bob', '2025-03-05 10:00:00+00', '192.0.2.2'),
+    ('dave', '2025-02-20 10:00:00+00', '192.0.2.3');
@@ -0,0 +1,809 @@
+//! End-to-end tests of the HTTP routes, each against its own database.
+//!
+//! The Postgres tests get a fresh database from `#[sqlx::test]`, migrated
+//! and seeded with the fixtures in `tests/fixtures`; they need
+//! `DATABASE_URL` to point at a server where databases can be created.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{header, Request, StatusCode};
+use axum::Router;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+use blort::auth::{self, AuthPolicy, Scope};
+use blort::clear::{self, ClearFilter};
+use blort::config::{AuthConfig, NameConfig, RateLimitBackend, RateLimitConfig};
+use blort::monitoring::Metrics;
+use blort::normalize::Normalizer;
+use blort::proxies::TrustedProxies;
+use blort::ratelimit::RateLimiter;
+use blort::shutdown::Shutdown;
+use blort::store::{MemoryStore, PgStore, Store, VisitContext};
+use blort::validate::NamePolicy;
+use blort::{aliases, record_visit, router, AppState};
+
+fn test_context() -> VisitContext {
+    VisitContext {
+        client_ip: Some("127.0.0.1".to_string()),
+        user_agent: None,
+        referer: None,
+    }
+}
+
+/// The application router on `store`, with the default name policy, the
+/// given rate limits and the reverse proxies in `trusted_proxies`.
+fn proxied_app(store: Store, limits: &RateLimitConfig, trusted_proxies: &str) -> Router {
+    let config = NameConfig::default();
+    let normalizer = Normalizer::from_steps(&config.normalization).unwrap();
+    let policy = NamePolicy::new(&config, &normalizer).unwrap();
+
+    router(AppState {
+        limiter: RateLimiter::new(limits, &*store).unwrap(),
+        store,
+        normalizer,
+        policy,
+        metrics: Metrics::install().unwrap(),
+        auth: AuthPolicy::new(&AuthConfig::default()).unwrap(),
+        proxies: TrustedProxies::parse(trusted_proxies).unwrap(),
+        shutdown: Shutdown::default(),
+    })
+}
+
+fn limited_app(store: Store, limits: &RateLimitConfig) -> Router {
+    proxied_app(store, limits, "")
+}
+
+/// The application router on `store`, with the default configuration.
+fn app(store: Store) -> Router {
+    limited_app(store, &RateLimitConfig::default())
+}
+
+fn pg_app(db: PgPool) -> Router {
+    app(Arc::new(PgStore::new(db)))
+}
+
+async fn send(app: &Router, request: Request<Body>) -> (StatusCode, String) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+async fn get_text(app: &Router, uri: &str) -> (StatusCode, String) {
+    send(app, Request::get(uri).body(Body::empty()).unwrap()).await
+}
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+    let request = Request::get(uri)
+        .header(header::ACCEPT, "application/json")
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(app, request).await;
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+/// `uri` requested with `method` and, if given, a bearer `key`.
+async fn send_with_key(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    key: Option<&str>,
+) -> (StatusCode, String) {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(key) = key {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {key}"));
+    }
+    send(app, request.body(Body::empty()).unwrap()).await
+}
+
+/// Names of a `GET /names` page, in order.
+fn page_names(page: &Value) -> Vec<&str> {
+    page["names"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["name"].as_str().unwrap())
+        .collect()
+}
+
+#[sqlx::test]
+async fn first_visit_is_greeted(db: PgPool) {
+    let app = pg_app(db);
+
+    let (status, body) = get_text(&app, "/hello/%20Alice").await;
+    assert_eq!(status, StatusCode::OK);
+    insta::assert_snapshot!(body, @"Hello alice! This is your first visit!");
+
+    let (status, entry) = get_json(&app, "/names/ALICE").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(entry["count"], 1);
+    assert_eq!(entry["first_seen"], entry["last_seen"]);
+
+    // Text and JSON share the URL, so both tell caches they vary on Accept.
+    for accept in ["text/plain", "application/json"] {
+        let request = Request::get("/hello/alice")
+            .header(header::ACCEPT, accept)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[header::VARY], "Accept", "{accept}");
+    }
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn repeat_visits_report_the_previous_visit(db: PgPool) {
+    let app = pg_app(db);
+
+    let (status, body) = get_text(&app, "/hello/alice").await;
+    assert_eq!(status, StatusCode::OK);
+    insta::assert_snapshot!(
+        body,
+        @"Hello alice! You've been called 5 times previously. Last seen: 2025-03-01 10:00:00"
+    );
+
+    let (_, body) = get_text(&app, "/hello/bob?tz=Europe/Paris").await;
+    insta::assert_snapshot!(
+        body,
+        @"Hello bob! You've been called 2 times previously. Last seen: 2025-03-05 11:00:00 CET"
+    );
+
+    let (status, visit) = get_json(&app, "/hello/dave?tz=America/New_York").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        visit,
+        json!({
+            "name": "dave",
+            "previous_count": 1,
+            "new_count": 2,
+            "first_visit": false,
+            "duplicate": false,
+            "last_seen": "2025-02-20T05:00:00-05:00",
+        })
+    );
+
+    let (_, visit) = get_json(&app, "/api/v1/hello/dave").await;
+    assert_eq!(visit["previous_count"], 2);
+    assert_eq!(visit["new_count"], 3);
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn names_are_listed_in_every_order(db: PgPool) {
+    let app = pg_app(db);
+
+    let cases = [
+        ("/names", ["bob", "alice", "dave", "carol"]),
+        ("/names?direction=asc", ["carol", "dave", "alice", "bob"]),
+        ("/names?order=visits", ["carol", "alice", "bob", "dave"]),
+        (
+            "/names?order=visits&direction=asc",
+            ["dave", "bob", "alice", "carol"],
+        ),
+        ("/names?order=name", ["alice", "bob", "carol", "dave"]),
+        (
+            "/names?order=name&direction=desc",
+            ["dave", "carol", "bob", "alice"],
+        ),
+        ("/names?order=first_seen", ["dave", "bob", "alice", "carol"]),
+        (
+            "/names?order=first_seen&direction=asc",
+            ["carol", "alice", "bob", "dave"],
+        ),
+    ];
+    for (uri, expected) in cases {
+        let (status, page) = get_json(&app, uri).await;
+        assert_eq!(status, StatusCode::OK, "{uri}");
+        assert_eq!(page_names(&page), expected, "{uri}");
+    }
+
+    let (_, page) = get_json(&app, "/names?order=visits&min_visits=2&name_prefix=B").await;
+    assert_eq!(page_names(&page), ["bob"]);
+
+    // Offsets beyond i32 are valid, if past the end.
+    let (status, page) = get_json(&app, "/names?offset=3000000000").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page_names(&page), Vec::<&str>::new());
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn cursors_page_through_ties(db: PgPool) {
+    let app = pg_app(db);
+
+    let mut names = Vec::new();
+    let mut uri = "/names?order=visits&limit=1".to_string();
+    loop {
+        let (status, page) = get_json(&app, &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        names.extend(page_names(&page).into_iter().map(str::to_owned));
+        match page["next_cursor"].as_str() {
+            Some(cursor) => uri = format!("/names?order=visits&limit=1&cursor={cursor}"),
+            None => break,
+        }
+    }
+
+    assert_eq!(names, ["carol", "alice", "bob", "dave"]);
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn clear_removes_matching_names_and_visits(db: PgPool) {
+    let store: Store = Arc::new(PgStore::new(db.clone()));
+    let app = app(store.clone());
+
+    let filter = ClearFilter {
+        name: None,
+        seen_before: None,
+        max_count: Some(2),
+    };
+    clear::clear(&*store, &filter, None, true).await.unwrap();
+
+    let (_, page) = get_json(&app, "/names?order=name").await;
+    assert_eq!(page_names(&page), ["alice", "carol"]);
+    let (status, _) = get_json(&app, "/names/bob").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let remaining: Vec<String> = sqlx::query_scalar("SELECT DISTINCT name FROM visits")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    assert_eq!(remaining, ["alice"]);
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn merged_names_stay_merged(db: PgPool) {
+    let app = pg_app(db.clone());
+    let normalizer = Normalizer::from_steps(&NameConfig::default().normalization).unwrap();
+
+    aliases::merge_names(&db, &normalizer, "Bob", "alice")
+        .await
+        .unwrap();
+    let (_, alice) = get_json(&app, "/names/alice").await;
+    assert_eq!(alice["count"], 5 + 2);
+
+    // Later visits to the merged name keep going to its new home.
+    let (_, visit) = get_json(&app, "/hello/bob").await;
+    assert_eq!(visit["name"], "alice");
+    assert_eq!(visit["new_count"], 5 + 2 + 1);
+    let (_, page) = get_json(&app, "/names?order=name").await;
+    assert_eq!(page_names(&page), ["alice", "carol", "dave"]);
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn clear_dumps_the_deleted_rows(db: PgPool) {
+    let store: Store = Arc::new(PgStore::new(db.clone()));
+    let dump = std::env::temp_dir().join(format!("blort-dump-{}.ndjson", std::process::id()));
+    let _ = std::fs::remove_file(&dump);
+
+    let filter = ClearFilter {
+        name: None,
+        seen_before: None,
+        max_count: Some(2),
+    };
+    clear::clear(&*store, &filter, Some(&dump), true)
+        .await
+        .unwrap();
+    let written = std::fs::read_to_string(&dump).unwrap();
+    let records: Vec<Value> = written
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    // Visits come first, in no particular order within each table.
+    let names = |table: &str| {
+        let mut names: Vec<&str> = records
+            .iter()
+            .filter(|record| record["table"] == table)
+            .map(|record| record["name"].as_str().unwrap())
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(names("visit"), ["bob", "bob", "dave"]);
+    assert_eq!(names("item"), ["bob", "dave"]);
+    assert!(records[..3].iter().all(|record| record["table"] == "visit"));
+
+    // An existing dump is never overwritten, and nothing is deleted then.
+    let everything = ClearFilter {
+        name: None,
+        seen_before: None,
+        max_count: None,
+    };
+    assert!(clear::clear(&*store, &everything, Some(&dump), true)
+        .await
+        .is_err());
+    std::fs::remove_file(&dump).unwrap();
+    assert_eq!(store.count(&everything).await.unwrap(), (2, 2));
+
+    let cleared = store.clear(&everything, None).await.unwrap();
+    assert_eq!((cleared.names, cleared.visits), (2, 2));
+    assert_eq!(store.count(&everything).await.unwrap(), (0, 0));
+
+    // A failed deletion leaves no dump behind to block the retry.
+    db.close().await;
+    assert!(clear::clear(&*store, &everything, Some(&dump), true)
+        .await
+        .is_err());
+    assert!(!dump.exists());
+}
+
+/// `(start, visits)` of each bucket of a `GET /stats/{name}` response.
+fn buckets(stats: &Value) -> Vec<(&str, i64)> {
+    stats["buckets"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|bucket| {
+            (
+                bucket["start"].as_str().unwrap(),
+                bucket["visits"].as_i64().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn stats_fill_every_bucket_of_the_range(db: PgPool) {
+    let app = pg_app(db);
+
+    let (status, stats) = get_json(
+        &app,
+        "/stats/alice?since=2025-02-26T00:00:00Z&until=2025-03-02T00:00:00Z",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stats["total"], 2);
+    let visits: Vec<i64> = buckets(&stats).iter().map(|(_, visits)| *visits).collect();
+    assert_eq!(visits, [0, 1, 0, 1]);
+
+    // Days start at midnight in the requested zone, not in UTC.
+    let (_, stats) = get_json(
+        &app,
+        "/stats/alice?tz=America/New_York\
+         &since=2025-02-27T05:00:00Z&until=2025-03-02T05:00:00Z",
+    )
+    .await;
+    assert_eq!(
+        buckets(&stats),
+        [
+            ("2025-02-27T00:00:00-05:00", 1),
+            ("2025-02-28T00:00:00-05:00", 0),
+            ("2025-03-01T00:00:00-05:00", 1),
+        ]
+    );
+
+    // Weeks start on Monday at midnight, here in Paris.
+    let (_, stats) = get_json(
+        &app,
+        "/stats/bob?granularity=week&tz=Europe/Paris\
+         &since=2025-02-20T00:00:00Z&until=2025-03-09T23:00:00Z",
+    )
+    .await;
+    assert_eq!(
+        buckets(&stats),
+        [
+            ("2025-02-17T00:00:00+01:00", 0),
+            ("2025-02-24T00:00:00+01:00", 0),
+            ("2025-03-03T00:00:00+01:00", 1),
+        ]
+    );
+}
+
+#[sqlx::test]
+async fn stats_reject_unreasonable_ranges(db: PgPool) {
+    let app = pg_app(db);
+
+    let cases = [
+        // More than 10,000 hourly buckets.
+        "/stats/alice?granularity=hour&since=60w",
+        "/stats/alice?since=2025-03-01T00:00:00Z&until=2025-02-01T00:00:00Z",
+        "/stats/alice?since=9999999999999w",
+        "/stats/alice?since=100000000d",
+    ];
+    for uri in cases {
+        let (status, body) = get_json(&app, uri).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        assert_eq!(body["error"], "invalid_request", "{uri}");
+    }
+
+    let (status, _) = get_json(&app, "/stats/alice?granularity=hour&since=52w").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[sqlx::test]
+async fn invalid_requests_are_rejected(db: PgPool) {
+    let app = pg_app(db.clone());
+
+    let long_name = "a".repeat(65);
+    let cases = [
+        (
+            "/hello/%E2%80%8B",
+            StatusCode::BAD_REQUEST,
+            "invalid_character",
+        ),
+        ("/hello/%20", StatusCode::BAD_REQUEST, "empty_name"),
+        (
+            &format!("/hello/{long_name}"),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "name_too_long",
+        ),
+        (
+            "/names?cursor=oops",
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+        ),
+        (
+            "/names?since=yesterday",
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+        ),
+        (
+            "/names?since=9999999999999w",
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+        ),
+        ("/names/nobody", StatusCode::NOT_FOUND, "not_found"),
+    ];
+    for (uri, expected_status, expected_error) in cases {
+        let (status, body) = get_json(&app, uri).await;
+        assert_eq!(status, expected_status, "{uri}");
+        assert_eq!(body["error"], expected_error, "{uri}");
+    }
+
+    // Malformed query strings get the same JSON body as any other 4xx.
+    for uri in [
+        "/hello/alice?tz=Mars/Olympus",
+        "/api/v1/hello/alice?tz=Mars/Olympus",
+        "/names?tz=Mars/Olympus",
+        "/names?limit=many",
+        "/names/alice?tz=Mars/Olympus",
+        "/stats/alice?granularity=fortnight",
+        "/stats/alice?tz=Mars/Olympus",
+    ] {
+        let (status, body) = get_json(&app, uri).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        assert_eq!(body["error"], "invalid_request", "{uri}");
+        assert!(
+            body["message"].as_str().unwrap().contains("query string"),
+            "{uri}: {body}"
+        );
+    }
+
+    // Nothing was counted along the way.
+    let (_, page) = get_json(&app, "/names").await;
+    assert_eq!(page_names(&page), Vec::<&str>::new());
+
+    db.close().await;
+    let (status, body) = get_json(&app, "/hello/alice").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["error"], "unavailable");
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn protected_routes_require_a_key_with_their_scope(db: PgPool) {
+    let store: Store = Arc::new(PgStore::new(db.clone()));
+    let app = app(store.clone());
+    let reader = auth::insert_key(&*store, "reader", &[Scope::Read])
+        .await
+        .unwrap();
+    let exporter = auth::insert_key(&*store, "exporter", &[Scope::Export])
+        .await
+        .unwrap();
+
+    let response = app
+        .clone()
+        .oneshot(Request::get("/export").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+
+    let cases = [
+        (Some("blort_unknown"), StatusCode::UNAUTHORIZED),
+        (Some(reader.key.as_str()), StatusCode::FORBIDDEN),
+        (Some(exporter.key.as_str()), StatusCode::OK),
+    ];
+    for (key, expected) in cases {
+        let (status, _) = send_with_key(&app, "GET", "/export", key).await;
+        assert_eq!(status, expected, "{key:?}");
+    }
+
+    let request = Request::get("/export?format=csv")
+        .header("x-api-key", &exporter.key)
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.starts_with("table,name,count,"), "{body}");
+    assert_eq!(
+        body.lines()
+            .filter(|line| line.starts_with("item,"))
+            .count(),
+        4
+    );
+
+    // Public scopes need no key, and ignore the one given.
+    let (status, _) = send_with_key(&app, "GET", "/hello/erin", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send_with_key(&app, "GET", "/names/erin", Some("blort_unknown")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert!(store.revoke_key(exporter.id).await.unwrap());
+    let (status, _) = send_with_key(&app, "GET", "/export", Some(&exporter.key)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let last_used =
+        sqlx::query_scalar!("SELECT last_used_at FROM api_keys WHERE id = $1", reader.id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+    assert!(last_used.is_some());
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn names_are_deleted_with_an_admin_key(db: PgPool) {
+    let store: Store = Arc::new(PgStore::new(db));
+    let app = app(store.clone());
+    let admin = auth::insert_key(&*store, "admin", &[Scope::Admin])
+        .await
+        .unwrap();
+
+    let (status, _) = send_with_key(&app, "DELETE", "/names/alice", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = send_with_key(&app, "DELETE", "/names/Alice", Some(&admin.key)).await;
+    assert_eq!(status, StatusCode::OK);
+    let deleted: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        deleted,
+        json!({"name": "alice", "count": 5, "deleted_visits": 2})
+    );
+
+    let (status, _) = get_json(&app, "/names/alice").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send_with_key(&app, "DELETE", "/names/alice", Some(&admin.key)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+/// A visit to `uri` over a connection from `peer`, which claims to forward
+/// `forwarded_for`.
+async fn visit_via(
+    app: &Router,
+    uri: &str,
+    peer: &str,
+    forwarded_for: Option<&str>,
+) -> axum::response::Response {
+    let mut request = Request::get(uri)
+        .header(header::ACCEPT, "application/json")
+        .extension(ConnectInfo(SocketAddr::new(peer.parse().unwrap(), 40000)));
+    if let Some(forwarded_for) = forwarded_for {
+        request = request.header("x-forwarded-for", forwarded_for);
+    }
+    app.clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+/// A visit to `uri` from `client_ip`, connected directly.
+async fn visit_from(app: &Router, uri: &str, client_ip: &str) -> axum::response::Response {
+    visit_via(app, uri, client_ip, None).await
+}
+
+#[sqlx::test]
+async fn visits_are_rate_limited_per_client_and_per_name(db: PgPool) {
+    sqlx::query("INSERT INTO aliases (alias, name) VALUES ('ally', 'alice')")
+        .execute(&db)
+        .await
+        .unwrap();
+    for backend in [RateLimitBackend::Memory, RateLimitBackend::Postgres] {
+        sqlx::query("TRUNCATE rate_limits")
+            .execute(&db)
+            .await
+            .unwrap();
+        let limits = RateLimitConfig {
+            per_client_per_minute: 1,
+            per_name_per_minute: 2,
+            burst: 2,
+            backend,
+            ..RateLimitConfig::default()
+        };
+        let app = limited_app(Arc::new(PgStore::new(db.clone())), &limits);
+
+        for uri in ["/hello/alice", "/hello/ally"] {
+            let response = visit_from(&app, uri, "192.0.2.1").await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = visit_from(&app, "/hello/bob", "192.0.2.1").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        // One visit a minute, the last one just used.
+        let retry_after: u64 = response.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((59..=60).contains(&retry_after), "{retry_after}");
+
+        // Another client is not limited, but alice has had her two visits,
+        // under either name.
+        let response = visit_from(&app, "/api/v1/hello/bob", "192.0.2.2").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        for uri in ["/hello/alice", "/hello/ally"] {
+            let response = visit_from(&app, uri, "192.0.2.3").await;
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+    }
+
+    let (_, alice) = get_json(&app(Arc::new(PgStore::new(db))), "/names/alice").await;
+    assert_eq!(alice["count"], 4);
+}
+
+#[sqlx::test]
+async fn clients_cannot_escape_limits_with_forwarded_headers(db: PgPool) {
+    let limits = RateLimitConfig {
+        per_client_per_minute: 1,
+        burst: 1,
+        ..RateLimitConfig::default()
+    };
+    let app = proxied_app(
+        Arc::new(PgStore::new(db.clone())),
+        &limits,
+        "10.0.0.0/8, 2001:db8::1",
+    );
+
+    // A direct client's own X-Forwarded-For is ignored.
+    let response = visit_via(&app, "/hello/alice", "192.0.2.1", Some("198.51.100.1")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = visit_via(&app, "/hello/alice", "192.0.2.1", Some("198.51.100.2")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Behind trusted proxies, the entries a client prepends are ignored too.
+    for (forwarded_for, status) in [
+        ("192.0.2.2", StatusCode::OK),
+        ("198.51.100.3, 192.0.2.2", StatusCode::TOO_MANY_REQUESTS),
+        (
+            "198.51.100.4, 192.0.2.2, 10.1.2.3",
+            StatusCode::TOO_MANY_REQUESTS,
+        ),
+        ("192.0.2.3, 10.1.2.3", StatusCode::OK),
+    ] {
+        let response = visit_via(&app, "/hello/alice", "10.0.0.1", Some(forwarded_for)).await;
+        assert_eq!(response.status(), status, "{forwarded_for}");
+    }
+    let response = visit_via(&app, "/hello/alice", "2001:db8::1", Some("192.0.2.3")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Visits are logged under the same client as they are limited.
+    let logged: Vec<String> = sqlx::query_scalar("SELECT client_ip FROM visits ORDER BY id")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    assert_eq!(logged, ["192.0.2.1", "192.0.2.2", "192.0.2.3"]);
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn duplicate_visits_are_answered_without_counting(db: PgPool) {
+    sqlx::query("INSERT INTO aliases (alias, name) VALUES ('davy', 'dave')")
+        .execute(&db)
+        .await
+        .unwrap();
+    for backend in [RateLimitBackend::Memory, RateLimitBackend::Postgres] {
+        let limits = RateLimitConfig {
+            duplicate_window_secs: 3600,
+            backend,
+            ..RateLimitConfig::default()
+        };
+        let app = limited_app(Arc::new(PgStore::new(db.clone())), &limits);
+
+        let response = visit_from(&app, "/hello/dave", "192.0.2.1").await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let counted: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(counted["duplicate"], false);
+
+        let response = visit_from(&app, "/hello/davy", "192.0.2.1").await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let repeated: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(repeated["duplicate"], true);
+        assert_eq!(repeated["new_count"], counted["new_count"]);
+        assert_eq!(repeated["previous_count"], counted["new_count"]);
+
+        let response = visit_from(&app, "/hello/dave", "192.0.2.2").await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // Each backend counted one visit per client, but logged all three.
+    let (_, dave) = get_json(&app(Arc::new(PgStore::new(db.clone()))), "/names/dave").await;
+    assert_eq!(dave["count"], 1 + 2 * 2);
+    let visits: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM visits WHERE name = 'dave'")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(visits, 1 + 2 * 3);
+}
+
+#[tokio::test]
+async fn memory_store_serves_the_same_routes() {
+    let app = app(Arc::new(MemoryStore::default()));
+
+    let (_, body) = get_text(&app, "/hello/Alice").await;
+    insta::assert_snapshot!(body, @"Hello alice! This is your first visit!");
+    let (_, visit) = get_json(&app, "/hello/alice").await;
+    assert_eq!(visit["previous_count"], 1);
+
+    let (status, stats) = get_json(&app, "/stats/Alice?granularity=hour").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stats["total"], 2);
+
+    let (status, _) = get_text(&app, "/readyz").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn memory_store_checks_api_keys() {
+    let store: Store = Arc::new(MemoryStore::default());
+    let app = app(store.clone());
+    record_visit(&*store, "alice".to_string(), test_context())
+        .await
+        .unwrap();
+    let reader = auth::insert_key(&*store, "reader", &[Scope::Read])
+        .await
+        .unwrap();
+    let admin = auth::insert_key(&*store, "admin", &[Scope::Admin])
+        .await
+        .unwrap();
+
+    let (status, _) = send_with_key(&app, "DELETE", "/names/alice", Some("blort_nope")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send_with_key(&app, "DELETE", "/names/alice", Some(&reader.key)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = send_with_key(&app, "DELETE", "/names/alice", Some(&admin.key)).await;
+    assert_eq!(status, StatusCode::OK);
+    let deleted: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        deleted,
+        json!({"name": "alice", "count": 1, "deleted_visits": 1})
+    );
+}
+
+#[sqlx::test]
+async fn concurrent_visits_report_gapless_counts(db: PgPool) {
+    const VISITS: i32 = 300;
+
+    let store: Store = Arc::new(PgStore::new(db.clone()));
+    let mut tasks = tokio::task::JoinSet::new();
+    for _ in 0..VISITS {
+        let store = store.clone();
+        tasks
+            .spawn(async move { record_visit(&*store, "alice".to_string(), test_context()).await });
+    }
+
+    let mut visits = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        visits.push(result.unwrap().unwrap());
+    }
+    visits.sort_by_key(|visit| visit.previous_count);
+
+    let previous_counts: Vec<i32> = visits.iter().map(|visit| visit.previous_count).collect();
+    assert_eq!(previous_counts, (0..VISITS).collect::<Vec<_>>());
+
+    assert!(visits[0].first_visit && visits[0].last_seen.is_none());
+    for visit in &visits[1..] {
+        assert_eq!(visit.new_count, visit.previous_count + 1);
+        assert!(!visit.first_visit);
+        assert!(visit.last_seen.is_some());
+    }
+
+    let logged = sqlx::query_scalar!("SELECT COUNT(*) FROM visits WHERE name = 'alice'")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(logged, Some(i64::from(VISITS)));
+}
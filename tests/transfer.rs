@@ -0,0 +1,203 @@
+//! Tests of `blort export` and `blort import`, each against its own database.
+//!
+//! Like the HTTP tests, they need `DATABASE_URL` to point at a server where
+//! databases can be created.
+
+use std::path::PathBuf;
+use std::pin::pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::TryStreamExt;
+use sqlx::PgPool;
+
+use blort::transfer::{self, Format, ImportMode, Record};
+
+/// A path in the temporary directory that no other test uses.
+fn temp_path(extension: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let number = NEXT.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!(
+        "blort-transfer-{}-{number}.{extension}",
+        std::process::id()
+    ))
+}
+
+/// Write `contents` to a new file named with `extension`.
+fn file_with(extension: &str, contents: &str) -> PathBuf {
+    let path = temp_path(extension);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// `(name, count)` of every item, by name.
+async fn counts(db: &PgPool) -> Vec<(String, i32)> {
+    sqlx::query_as("SELECT name, count FROM items ORDER BY name")
+        .fetch_all(db)
+        .await
+        .unwrap()
+}
+
+/// Number of visits of each name, by name.
+async fn visits(db: &PgPool) -> Vec<(String, i64)> {
+    sqlx::query_as("SELECT name, COUNT(*) FROM visits GROUP BY name ORDER BY name")
+        .fetch_all(db)
+        .await
+        .unwrap()
+}
+
+fn pairs(rows: &[(&str, i64)]) -> Vec<(String, i64)> {
+    rows.iter()
+        .map(|(name, count)| (name.to_string(), *count))
+        .collect()
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn exports_round_trip_in_every_format(db: PgPool) {
+    for (format, extension) in [
+        (Format::Csv, "csv"),
+        (Format::Json, "json"),
+        (Format::Ndjson, "ndjson"),
+    ] {
+        let exported = temp_path(extension);
+        transfer::export(&db, format, Some(&exported))
+            .await
+            .unwrap();
+        transfer::import(&db, &exported, None, ImportMode::Replace)
+            .await
+            .unwrap();
+
+        let reexported = temp_path(extension);
+        transfer::export(&db, format, Some(&reexported))
+            .await
+            .unwrap();
+        let original = std::fs::read_to_string(&exported).unwrap();
+        assert_eq!(std::fs::read_to_string(&reexported).unwrap(), original);
+        assert_eq!(original.matches("alice").count(), 3, "{extension}");
+
+        std::fs::remove_file(exported).unwrap();
+        std::fs::remove_file(reexported).unwrap();
+    }
+
+    assert_eq!(
+        visits(&db).await,
+        pairs(&[("alice", 2), ("bob", 2), ("dave", 1)])
+    );
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn merging_sums_counts_under_canonical_names(db: PgPool) {
+    sqlx::query("INSERT INTO aliases (alias, name) VALUES ('ally', 'alice')")
+        .execute(&db)
+        .await
+        .unwrap();
+    let file = file_with(
+        "ndjson",
+        r#"{"table":"item","name":"alice","count":3,"last_seen":"2025-03-10T10:00:00Z","previous_seen":null}
+{"table":"item","name":"ally","count":2,"last_seen":"2025-03-08T10:00:00Z","previous_seen":null}
+{"table":"item","name":"erin","count":1,"last_seen":"2025-03-09T10:00:00Z","previous_seen":null}
+{"table":"visit","name":"ally","visited_at":"2025-03-08T10:00:00Z","client_ip":null,"user_agent":null,"referer":null}
+"#,
+    );
+
+    transfer::import(&db, &file, None, ImportMode::Merge)
+        .await
+        .unwrap();
+    std::fs::remove_file(file).unwrap();
+
+    assert_eq!(
+        counts(&db).await,
+        [
+            ("alice".to_string(), 5 + 3 + 2),
+            ("bob".to_string(), 2),
+            ("carol".to_string(), 5),
+            ("dave".to_string(), 1),
+            ("erin".to_string(), 1),
+        ]
+    );
+    assert_eq!(
+        visits(&db).await,
+        pairs(&[("alice", 3), ("bob", 2), ("dave", 1)])
+    );
+
+    let (last_seen, previous_seen): (String, String) = sqlx::query_as(
+        "SELECT last_seen::text, previous_seen::text FROM items WHERE name = 'alice'",
+    )
+    .fetch_one(&db)
+    .await
+    .unwrap();
+    assert_eq!(last_seen, "2025-03-10 10:00:00+00");
+    assert_eq!(previous_seen, "2025-03-08 10:00:00+00");
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn replacing_drops_what_the_file_lacks(db: PgPool) {
+    let file = file_with(
+        "csv",
+        "table,name,count,first_seen,last_seen,previous_seen,visited_at,client_ip,user_agent,referer\n\
+         item,erin,1,2025-03-09T10:00:00Z,2025-03-09T10:00:00Z,,,,,\n\
+         visit,erin,,,,,2025-03-09T10:00:00Z,192.0.2.9,,\n",
+    );
+
+    transfer::import(&db, &file, None, ImportMode::Replace)
+        .await
+        .unwrap();
+    std::fs::remove_file(file).unwrap();
+
+    assert_eq!(counts(&db).await, [("erin".to_string(), 1)]);
+    assert_eq!(visits(&db).await, pairs(&[("erin", 1)]));
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn malformed_files_import_nothing(db: PgPool) {
+    let before = counts(&db).await;
+
+    for (extension, contents) in [
+        (
+            "ndjson",
+            "{\"table\":\"item\",\"name\":\"erin\",\"count\":1,\"last_seen\":\"2025-03-09T10:00:00Z\",\"previous_seen\":null}\n\
+             not json\n",
+        ),
+        (
+            "json",
+            "[{\"table\":\"item\",\"name\":\"erin\",\"count\":1,\"last_seen\":\"2025-03-09T10:00:00Z\",\"previous_seen\":null},\n\
+             {\"table\":\"item\",\"name\":\"frank\"}]\n",
+        ),
+    ] {
+        for mode in [ImportMode::Merge, ImportMode::Replace] {
+            let file = file_with(extension, contents);
+            let error = transfer::import(&db, &file, None, mode)
+                .await
+                .unwrap_err();
+            std::fs::remove_file(&file).unwrap();
+            assert!(error.to_string().contains("Cannot import"), "{error}");
+
+            assert_eq!(counts(&db).await, before);
+            assert_eq!(
+                visits(&db).await,
+                pairs(&[("alice", 2), ("bob", 2), ("dave", 1)])
+            );
+        }
+    }
+}
+
+#[sqlx::test(fixtures("names"))]
+async fn exports_read_a_single_snapshot(db: PgPool) {
+    let mut records = pin!(transfer::records(&db));
+    assert!(matches!(
+        records.try_next().await.unwrap(),
+        Some(Record::Item(item)) if item.name == "alice"
+    ));
+
+    // Written once the export started, so left out of it.
+    sqlx::query("INSERT INTO visits (name, visited_at) VALUES ('alice', NOW())")
+        .execute(&db)
+        .await
+        .unwrap();
+
+    let rest: Vec<Record> = records.try_collect().await.unwrap();
+    let exported_visits = rest
+        .iter()
+        .filter(|record| matches!(record, Record::Visit(_)))
+        .count();
+    assert_eq!(exported_visits, 5);
+}